@@ -0,0 +1,125 @@
+use std::collections::{ BTreeSet, HashMap };
+use std::str::FromStr;
+
+use anyhow::{ anyhow, Result };
+use serde::Deserialize;
+use tokio::time::{ self, Duration };
+
+use crate::error::FetchError;
+use crate::notifier::Announcement;
+use crate::state::UnlockedAchievements;
+use crate::steam::{ steam_get_json, STEAM64_BASE };
+use crate::{
+    announce, render_template, session, target_name, EventKind, ACHIEVEMENTS_FILE, FEATURES,
+    LOCALIZATION, STEAM_API_KEY, STEAM_APP_IDS, TARGET_STEAMID32,
+};
+
+const STEAM_USER_STATS_URL: &str =
+    "https://api.steampowered.com/ISteamUserStats/GetPlayerAchievements/v1/";
+const ACHIEVEMENTS_POLL_INTERVAL: Duration = Duration::from_secs(300);
+
+/// `STEAM_APP_IDS` as `game name=app id` pairs separated by commas, mapping
+/// the game names Discord reports to Steam apps.
+#[derive(Debug, Clone, Default)]
+pub struct SteamApps(pub HashMap<String, u32>);
+
+impl FromStr for SteamApps {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.split(',')
+            .filter(|item| !item.trim().is_empty())
+            .map(|item| {
+                let (name, app_id) = item
+                    .rsplit_once('=')
+                    .ok_or_else(|| anyhow!("expected name=app id, got {item}"))?;
+                Ok((name.trim().to_string(), app_id.trim().parse()?))
+            })
+            .collect::<Result<_>>()
+            .map(SteamApps)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AchievementsResponse {
+    playerstats: PlayerStats,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlayerStats {
+    #[serde(default)]
+    achievements: Vec<Achievement>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Achievement {
+    apiname: String,
+    achieved: u8,
+    /// Display name, present because the request asks for a language.
+    #[serde(default)]
+    name: Option<String>,
+}
+
+async fn request_achievements(app_id: u32) -> Result<Vec<Achievement>, FetchError> {
+    let steam_id = STEAM64_BASE + TARGET_STEAMID32.get().unwrap();
+    let url = format!("{STEAM_USER_STATS_URL}?steamid={steam_id}&appid={app_id}&l=en");
+    let response: AchievementsResponse = steam_get_json(&url).await?;
+    Ok(response.playerstats.achievements)
+}
+
+/// Announces achievements unlocked in the game the target is playing, while
+/// that game is one of `STEAM_APP_IDS`.
+pub async fn achievements_loop() {
+    if STEAM_API_KEY.get().unwrap().is_empty() {
+        if FEATURES.get().unwrap().steam_achievements {
+            eprintln!("steam_achievements needs STEAM_API_KEY, achievement polling disabled");
+        }
+        return;
+    }
+    let path = ACHIEVEMENTS_FILE.get().unwrap();
+    let mut unlocked = UnlockedAchievements::load(path);
+
+    let mut interval = time::interval(ACHIEVEMENTS_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if !FEATURES.get().unwrap().steam_achievements {
+            continue;
+        }
+        let Some(game) = session::current_game() else {
+            continue;
+        };
+        let Some(&app_id) = STEAM_APP_IDS.get().unwrap().0.get(&game) else {
+            continue;
+        };
+
+        let achievements = match request_achievements(app_id).await {
+            Ok(achievements) => achievements,
+            Err(err) => {
+                eprintln!("Couldn't fetch achievements for app {app_id}: {err}");
+                continue;
+            }
+        };
+        let achieved: Vec<&Achievement> =
+            achievements.iter().filter(|achievement| achievement.achieved == 1).collect();
+        let ids: BTreeSet<String> =
+            achieved.iter().map(|achievement| achievement.apiname.clone()).collect();
+        let new = unlocked.update(app_id, ids);
+        if let Err(err) = unlocked.save(path) {
+            eprintln!("Couldn't save achievements to {}: {err}", path.display());
+        }
+
+        let template = LOCALIZATION.get().unwrap().achievement_unlocked.as_deref();
+        for id in new {
+            let name = achieved
+                .iter()
+                .find(|achievement| achievement.apiname == id)
+                .and_then(|achievement| achievement.name.clone())
+                .unwrap_or(id);
+            let content = render_template(
+                template.unwrap_or("{target_name} unlocked '{achievement}' in {game}"),
+                &[("target_name", &target_name()), ("achievement", &name), ("game", &game)],
+            );
+            announce(Announcement::new(EventKind::Presence, content)).await;
+        }
+    }
+}
@@ -0,0 +1,778 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateAttachment, CreateCommand,
+    CreateCommandOption, CreateInteractionResponse, CreateInteractionResponseMessage, GuildId,
+    OnlineStatus,
+};
+use tokio::time::{ Duration, Instant };
+
+use crate::dota_presence::{ self, DotaPresence, Side, DOTA_ACTIVITY_NAME };
+use crate::error::FetchError;
+use crate::notifier::{ Announcement, Notifier };
+use crate::reload;
+use crate::state::{ KdaRecord, LeaderboardChange, NewRecord };
+use crate::{
+    clamp_message, format_leaderboard_change, format_match, format_record, logoff_summary,
+    match_result, render_template, replay_announcement, set_heroes, target_name, update_state,
+    AnnouncementRecord, EventKind, MatchData, ACHIEVEMENTS_FILE, ACTIVE_CHANNEL_WINDOW_SECS,
+    ADMIN_USERS, ALLOWED_ACTIVITY_APP_IDS, ALT_ACCOUNTS, ANNOUNCEMENT_HISTORY,
+    ANNOUNCE_EVERY_N_MATCHES, ANNOUNCE_RESULTS, CURRENT_STATE, DISCORD_CONTENT_LIMIT,
+    DOTA_IDLE_MINUTES, DOTA_POLL_STATS, EXTERNAL_WEBHOOK_URL, FALLBACK_REACTION, FEATURES,
+    FIRST_BLOOD_MAX_SECS, FIRST_MATCH_GAP_HOURS, GAME_REANNOUNCE_SECS, HEARTBREAKER_MIN_MINUTES,
+    HEROES, HERO_DUST_DAYS, HIGH_KILLS, HISTORY_CAP, KDA_STYLE, LIBRARY_FILE,
+    LIVE_SCORE_INTERVAL_SECS, LIVE_SCORE_MIN_CHANGE, LOCALIZATION, LONG_SESSION_HOURS,
+    MAIN_LOOP_INTERVAL, MATCH_ANNOUNCE_DELAY_SECS, MATCH_EMBED_COLORS, MATCH_LRU_CAP,
+    MATCH_MILESTONES, MATCH_PREFIX, MATCH_SOURCE, MAX_MESSAGE_LENGTH, MUTED_UNTIL, NOTIFIERS,
+    OPENDOTA_BASE_URL, OPENDOTA_RATE_PER_MINUTE, OUTPUT_CHANNEL, PRESENCE_PREFIX, REACTION,
+    RESOLVED_REACTION, ROUTES, SEEN_MATCHES_FILE, STATE_FILE, STEAM_APP_IDS, STEAM_GROUP_ID,
+    STEAM_GROUP_MAX_MEMBERS, STOMP_MAX_MINUTES, TARGET_GUILD, TARGET_LEFT_GUILD, TARGET_STEAMID32,
+    TARGET_USER, TEAMMATE_USERS,
+};
+
+const DEFAULT_MUTE_MINUTES: u64 = 60;
+const DEFAULT_EXPORT_COUNT: usize = 20;
+
+pub async fn register(ctx: &Context) {
+    let mut commands = vec![
+        CreateCommand::new("config").description("Show the effective bot configuration"),
+        CreateCommand::new("now").description("Show what the bot thinks the target is doing"),
+        CreateCommand::new("health").description("Show polling health"),
+        CreateCommand::new("mute")
+            .description("Silence automatic announcements for a while")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "minutes", "Default 60")
+                    .min_int_value(1),
+            ),
+        CreateCommand::new("unmute").description("End a /mute early"),
+        CreateCommand::new("reload")
+            .description("Re-read localization.json, features.json and routes.json"),
+        CreateCommand::new("refresh-data")
+            .description("Re-fetch the hero list, e.g. after a patch added heroes"),
+        CreateCommand::new("test")
+            .description("Send a sample announcement to check channels and permissions")
+            .add_option(kind_option("Route to test, default match")),
+        CreateCommand::new("preview")
+            .description("Render a sample of every announcement with the current localization"),
+        CreateCommand::new("export")
+            .description("Attach the most recent announcements as a JSON file")
+            .add_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "count", "Default 20")
+                    .min_int_value(1),
+            ),
+        CreateCommand::new("replay")
+            .description("Repost the most recent announcements")
+            .add_option(kind_option("Only replay this kind of announcement")),
+    ];
+    if FEATURES.get().unwrap().debug_commands {
+        commands.push(
+            CreateCommand::new("setstreak")
+                .description("Overwrite the persisted streak (negative for losses)")
+                .add_option(
+                    CreateCommandOption::new(CommandOptionType::Integer, "streak", "New streak")
+                        .required(true),
+                ),
+        );
+        commands.push(
+            CreateCommand::new("setresult")
+                .description("Overwrite the persisted last match result")
+                .add_option(
+                    CreateCommandOption::new(CommandOptionType::String, "result", "New result")
+                        .add_string_choice("win", "win")
+                        .add_string_choice("loss", "loss")
+                        .required(true),
+                ),
+        );
+    }
+    if let Err(why) = GuildId::new(*TARGET_GUILD.get().unwrap())
+        .set_commands(&ctx.http, commands)
+        .await
+    {
+        eprintln!("Error registering commands: {why:?}");
+    }
+}
+
+pub async fn handle(ctx: &Context, command: &CommandInteraction) {
+    let mut attachment = None;
+    let content = if !is_admin(command) {
+        "This command is restricted to bot admins.".to_string()
+    } else {
+        match command.data.name.as_str() {
+            "config" => {
+                let (content, file) = config();
+                attachment = file;
+                content
+            }
+            "now" => now(),
+            "health" => health(),
+            "mute" => mute(command),
+            "unmute" => unmute(),
+            "reload" => reload(ctx).await,
+            "replay" => replay(command).await,
+            "refresh-data" => refresh_data().await,
+            "preview" => preview(),
+            "test" => test(command).await,
+            "export" => {
+                let (content, file) = export(command);
+                attachment = file;
+                content
+            }
+            "setstreak" if FEATURES.get().unwrap().debug_commands => set_streak(command),
+            "setresult" if FEATURES.get().unwrap().debug_commands => set_result(command),
+            _ => return,
+        }
+    };
+
+    let mut message = CreateInteractionResponseMessage::new()
+        .content(content)
+        .ephemeral(true);
+    if let Some(attachment) = attachment {
+        message = message.add_file(attachment);
+    }
+    let response = CreateInteractionResponse::Message(message);
+    if let Err(why) = command.create_response(&ctx.http, response).await {
+        eprintln!("Error responding to /{}: {why:?}", command.data.name);
+    }
+}
+
+fn kind_option(description: &str) -> CreateCommandOption {
+    let mut option = CreateCommandOption::new(CommandOptionType::String, "kind", description);
+    for kind in EventKind::ALL {
+        option = option.add_string_choice(kind.key(), kind.key());
+    }
+    option
+}
+
+fn string_option<'a>(command: &'a CommandInteraction, name: &str) -> Option<&'a str> {
+    command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| option.value.as_str())
+}
+
+fn is_admin(command: &CommandInteraction) -> bool {
+    ADMIN_USERS.get().unwrap().contains(&command.user.id.get())
+}
+
+/// Masks a secret so only whether it is set is revealed.
+fn redact(secret: Option<&str>) -> &'static str {
+    match secret {
+        Some(secret) if !secret.is_empty() => "<redacted>",
+        _ => "<unset>",
+    }
+}
+
+/// `name` and whether it or its `_FILE` variant is set, never the secret itself.
+fn secret_line(name: &str) -> String {
+    let secret = std::env::var(format!("{name}_FILE"))
+        .or_else(|_| std::env::var(name))
+        .ok();
+    format!("{name} = {}", redact(secret.as_deref()))
+}
+
+/// The effective configuration, attached as a file once it no longer fits
+/// in a message.
+fn config() -> (String, Option<CreateAttachment>) {
+    let mut lines = vec![
+        secret_line("DISCORD_TOKEN"),
+        format!(
+            "EXTERNAL_WEBHOOK_URL = {}",
+            redact(EXTERNAL_WEBHOOK_URL.get().map(String::as_str))
+        ),
+        format!("MAIN_LOOP_INTERVAL = {}s", MAIN_LOOP_INTERVAL.as_secs()),
+        format!("TARGET_GUILD = {}", TARGET_GUILD.get().unwrap()),
+        format!("OUTPUT_CHANNEL = {:?}", OUTPUT_CHANNEL.get().unwrap()),
+        format!("TARGET_USER = {}", TARGET_USER.get().unwrap()),
+        format!("TARGET_STEAMID32 = {}", TARGET_STEAMID32.get().unwrap()),
+        format!("ALT_ACCOUNTS = {:?}", ALT_ACCOUNTS.get().unwrap().0),
+        format!("TEAMMATE_USERS = {:?}", TEAMMATE_USERS.get().unwrap().0),
+        format!("REACTION = {}", REACTION.get().unwrap()),
+        format!(
+            "FALLBACK_REACTION = {}",
+            FALLBACK_REACTION
+                .get()
+                .unwrap()
+                .as_ref()
+                .map_or("none".to_string(), ToString::to_string)
+        ),
+        format!(
+            "resolved reaction = {}",
+            RESOLVED_REACTION
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map_or("none".to_string(), ToString::to_string)
+        ),
+        format!("MATCH_PREFIX = {:?}", MATCH_PREFIX.get().unwrap()),
+        format!("PRESENCE_PREFIX = {:?}", PRESENCE_PREFIX.get().unwrap()),
+        secret_line("STEAM_API_KEY"),
+        format!("MAX_MESSAGE_LENGTH = {}", MAX_MESSAGE_LENGTH.get().unwrap()),
+        format!("ADMIN_USERS = {:?}", ADMIN_USERS.get().unwrap()),
+        format!("STOMP_MAX_MINUTES = {}", STOMP_MAX_MINUTES.get().unwrap()),
+        format!(
+            "HEARTBREAKER_MIN_MINUTES = {}",
+            HEARTBREAKER_MIN_MINUTES.get().unwrap()
+        ),
+        format!("HIGH_KILLS = {}", HIGH_KILLS.get().unwrap()),
+        format!(
+            "FIRST_BLOOD_MAX_SECS = {}",
+            FIRST_BLOOD_MAX_SECS.get().unwrap()
+        ),
+        format!("HERO_DUST_DAYS = {}", HERO_DUST_DAYS.get().unwrap()),
+        format!(
+            "ACTIVE_CHANNEL_WINDOW_SECS = {}",
+            ACTIVE_CHANNEL_WINDOW_SECS.get().unwrap()
+        ),
+        format!(
+            "MATCH_EMBED_COLORS = {:?}",
+            MATCH_EMBED_COLORS.get().unwrap()
+        ),
+        format!("MATCH_SOURCE = {:?}", MATCH_SOURCE.get().unwrap()),
+        format!("KDA_STYLE = {:?}", KDA_STYLE.get().unwrap()),
+        format!(
+            "MATCH_ANNOUNCE_DELAY_SECS = {}",
+            MATCH_ANNOUNCE_DELAY_SECS.get().unwrap()
+        ),
+        format!(
+            "GAME_REANNOUNCE_SECS = {}",
+            GAME_REANNOUNCE_SECS.get().unwrap()
+        ),
+        format!("HISTORY_CAP = {}", HISTORY_CAP.get().unwrap()),
+        format!("MATCH_LRU_CAP = {}", MATCH_LRU_CAP.get().unwrap()),
+        format!(
+            "ANNOUNCE_EVERY_N_MATCHES = {}",
+            ANNOUNCE_EVERY_N_MATCHES.get().unwrap()
+        ),
+        format!("OPENDOTA_BASE_URL = {}", OPENDOTA_BASE_URL.get().unwrap()),
+        format!(
+            "OPENDOTA_RATE_PER_MINUTE = {}",
+            OPENDOTA_RATE_PER_MINUTE.get().unwrap()
+        ),
+        format!("ANNOUNCE_RESULTS = {:?}", ANNOUNCE_RESULTS.get().unwrap()),
+        format!("MATCH_MILESTONES = {:?}", MATCH_MILESTONES.get().unwrap()),
+        format!(
+            "LONG_SESSION_HOURS = {:?}",
+            LONG_SESSION_HOURS.get().unwrap()
+        ),
+        format!("DOTA_IDLE_MINUTES = {}", DOTA_IDLE_MINUTES.get().unwrap()),
+        format!(
+            "FIRST_MATCH_GAP_HOURS = {}",
+            FIRST_MATCH_GAP_HOURS.get().unwrap()
+        ),
+        format!(
+            "ALLOWED_ACTIVITY_APP_IDS = {:?}",
+            ALLOWED_ACTIVITY_APP_IDS.get().unwrap()
+        ),
+        format!("STATE_FILE = {}", STATE_FILE.get().unwrap().display()),
+        format!(
+            "SEEN_MATCHES_FILE = {}",
+            SEEN_MATCHES_FILE.get().unwrap().display()
+        ),
+        format!("LIBRARY_FILE = {}", LIBRARY_FILE.get().unwrap().display()),
+        format!(
+            "ACHIEVEMENTS_FILE = {}",
+            ACHIEVEMENTS_FILE.get().unwrap().display()
+        ),
+        format!("STEAM_APP_IDS = {:?}", STEAM_APP_IDS.get().unwrap().0),
+        format!("STEAM_GROUP_ID = {}", STEAM_GROUP_ID.get().unwrap()),
+        format!(
+            "STEAM_GROUP_MAX_MEMBERS = {}",
+            STEAM_GROUP_MAX_MEMBERS.get().unwrap()
+        ),
+        format!(
+            "LIVE_SCORE_INTERVAL_SECS = {}",
+            LIVE_SCORE_INTERVAL_SECS.get().unwrap()
+        ),
+        format!(
+            "LIVE_SCORE_MIN_CHANGE = {}",
+            LIVE_SCORE_MIN_CHANGE.get().unwrap()
+        ),
+    ];
+    lines.push(format!("FEATURES = {:?}", FEATURES.get().unwrap()));
+    let table = ROUTES.get().unwrap();
+    // The tables are hash maps, sort them so repeated calls are comparable.
+    let mut routes: Vec<String> = table
+        .routes
+        .iter()
+        .map(|(kind, route)| format!("route {} = {route:?}", kind.name()))
+        .chain(table.party_match.iter().map(|route| format!("route party_match = {route:?}")))
+        .collect();
+    routes.sort();
+    let mut subscriptions: Vec<String> = table
+        .subscriptions
+        .iter()
+        .map(|(channel, kinds)| format!("subscriptions {channel} = {kinds:?}"))
+        .collect();
+    subscriptions.sort();
+    lines.extend(routes);
+    lines.extend(subscriptions);
+
+    let text = lines.join("\n");
+    let content = format!("```\n{text}\n```");
+    if content.chars().count() <= DISCORD_CONTENT_LIMIT {
+        return (content, None);
+    }
+    (
+        "The configuration is too long for a message, it's attached.".to_string(),
+        Some(CreateAttachment::bytes(text.into_bytes(), "config.txt")),
+    )
+}
+
+fn now() -> String {
+    if *TARGET_LEFT_GUILD.lock().unwrap() {
+        return "The target left the server; waiting for them to rejoin.".to_string();
+    }
+    let Some(state) = CURRENT_STATE.lock().unwrap().clone() else {
+        return "No presence update seen yet.".to_string();
+    };
+    let locals = LOCALIZATION.get().unwrap();
+    let status: &str = get_string_for_status!(state.status);
+    let target_name = target_name();
+    match state.game {
+        Some(game) => format!("{target_name} {status} {} {game}", locals.plays),
+        None => format!("{target_name} {status}"),
+    }
+}
+
+async fn reload(ctx: &Context) -> String {
+    match reload::reload() {
+        Ok(changed) if changed.is_empty() => "Reloaded, nothing changed.".to_string(),
+        Ok(changed) => {
+            // Features decide which commands exist.
+            register(ctx).await;
+            format!("Reloaded, changed: {}.", changed.join(", "))
+        }
+        Err(err) => format!("Reload failed, keeping the current config: {err}"),
+    }
+}
+
+async fn refresh_data() -> String {
+    refresh_data_with(set_heroes()).await
+}
+
+/// Runs `refresh` and reports how many heroes it loaded.
+async fn refresh_data_with(refresh: impl Future<Output = Result<usize, FetchError>>) -> String {
+    match refresh.await {
+        Ok(count) => format!("Loaded {count} heroes."),
+        Err(err) => format!("Refreshing heroes failed, keeping the current list: {err}"),
+    }
+}
+
+/// Sends a canned announcement through every notifier, ignoring `/mute` and
+/// passive mode, and reports what failed.
+async fn test(command: &CommandInteraction) -> String {
+    let kind = match string_option(command, "kind") {
+        Some(key) => match EventKind::from_key(key) {
+            Some(kind) => kind,
+            None => return format!("Unknown announcement kind {key}."),
+        },
+        None => EventKind::Match,
+    };
+    send_test(kind, NOTIFIERS.get().unwrap()).await
+}
+
+/// Sends a canned `kind` announcement through `notifiers` and reports where
+/// it went or what failed.
+async fn send_test(kind: EventKind, notifiers: &[Box<dyn Notifier>]) -> String {
+    let content = format!(
+        "Test {} announcement from /test, the bot can post here.",
+        kind.key()
+    );
+    let announcement = Announcement::new(kind, content);
+    let mut failures = Vec::new();
+    for notifier in notifiers {
+        if let Err(err) = notifier.send(&announcement).await {
+            failures.push(err.to_string());
+        }
+    }
+    let channels = kind
+        .route()
+        .channels
+        .iter()
+        .map(|channel| format!("<#{channel}>"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if failures.is_empty() {
+        format!("Sent a test {} announcement to {channels}.", kind.key())
+    } else {
+        format!(
+            "Test {} announcement failed: {}",
+            kind.key(),
+            failures.join("; ")
+        )
+    }
+}
+
+async fn replay(command: &CommandInteraction) -> String {
+    let kinds = match string_option(command, "kind") {
+        Some(key) => match EventKind::from_key(key) {
+            Some(kind) => vec![kind],
+            None => return format!("Unknown announcement kind {key}."),
+        },
+        None => EventKind::ALL.to_vec(),
+    };
+
+    let mut replayed = Vec::new();
+    for kind in kinds {
+        if replay_announcement(kind).await {
+            replayed.push(kind.key());
+        }
+    }
+    if replayed.is_empty() {
+        "Nothing to replay yet.".to_string()
+    } else {
+        format!("Replayed: {}.", replayed.join(", "))
+    }
+}
+
+fn preview() -> String {
+    let Some(hero_id) = HEROES.get().and_then(|heroes| heroes.keys().min().copied()) else {
+        return "Heroes aren't loaded yet, try again after the first poll.".to_string();
+    };
+    let mut lines = Vec::new();
+    for kind in EventKind::ALL {
+        for sample in preview_samples(kind, hero_id) {
+            lines.push(format!("**{}**: {sample}", kind.key()));
+        }
+    }
+    clamp_message(&lines.join("\n"), DISCORD_CONTENT_LIMIT)
+}
+
+/// Renders the announcements of `kind` with canned data. Templates left out
+/// of the localization are skipped, just like when announcing for real.
+fn preview_samples(kind: EventKind, hero_id: i64) -> Vec<String> {
+    let locals = LOCALIZATION.get().unwrap();
+    let target = target_name();
+    match kind {
+        EventKind::Match => {
+            let win = MatchData {
+                match_id: 1,
+                hero_id,
+                radiant_win: Some(true),
+                duration: Some(35 * 60),
+                kills: Some(12),
+                deaths: Some(3),
+                assists: Some(15),
+                gold_per_min: Some(640),
+                ..MatchData::default()
+            };
+            let loss = MatchData {
+                radiant_win: Some(false),
+                ..win.clone()
+            };
+            let mut samples = vec![
+                format_match(&win, match_result(&win)),
+                format_match(&loss, match_result(&loss)),
+            ];
+            samples.extend(format_record(NewRecord::Streak(5)));
+            samples.extend(format_record(NewRecord::Milestone(500)));
+            samples.extend(format_record(NewRecord::Kda(KdaRecord {
+                match_id: 1,
+                kills: 12,
+                deaths: 3,
+                assists: 15,
+            })));
+            samples.extend(
+                [
+                    LeaderboardChange::Entered(900),
+                    LeaderboardChange::Moved { from: 900, to: 842 },
+                    LeaderboardChange::Left(1000),
+                ]
+                .into_iter()
+                .filter_map(format_leaderboard_change),
+            );
+            samples.extend(logoff_summary(&[win, loss]).map(|summary| summary.text));
+            samples
+        }
+        EventKind::Presence => {
+            let online: &str = get_string_for_status!(OnlineStatus::Online);
+            let mut samples = vec![
+                format!("{target} {online}"),
+                format!("{target} {online} {} {DOTA_ACTIVITY_NAME}", locals.plays),
+            ];
+            samples.extend(
+                [
+                    DotaPresence::Menu,
+                    DotaPresence::Searching,
+                    DotaPresence::InMatch(Some(Side::Radiant)),
+                ]
+                .into_iter()
+                .filter_map(dota_presence::format),
+            );
+            samples.extend(locals.custom_status.as_deref().map(|template| {
+                render_template(template, &[("target_name", &target), ("text", "brb")])
+            }));
+            samples
+        }
+        EventKind::Lifecycle => vec![
+            locals
+                .watcher_started
+                .as_deref()
+                .unwrap_or("Watcher started")
+                .to_string(),
+            locals
+                .watcher_stopping
+                .as_deref()
+                .unwrap_or("Watcher shutting down")
+                .to_string(),
+        ],
+    }
+}
+
+fn health() -> String {
+    let stats = DOTA_POLL_STATS.lock().unwrap().clone();
+    let last_success = match stats.last_success {
+        Some(time) => {
+            let secs = time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            format!("<t:{secs}:R>")
+        }
+        None => "never".to_string(),
+    };
+    format!(
+        "OpenDota poll: last success {last_success}, {} consecutive errors, {} total errors",
+        stats.consecutive_errors, stats.total_errors,
+    )
+}
+
+fn mute(command: &CommandInteraction) -> String {
+    let minutes = command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == "minutes")
+        .and_then(|option| option.value.as_i64());
+    let duration = Duration::from_secs(mute_minutes(minutes) * 60);
+    *MUTED_UNTIL.lock().unwrap() = Some(Instant::now() + duration);
+    let until = (SystemTime::now() + duration)
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    format!("Muted until <t:{until}:t> (<t:{until}:R>).")
+}
+
+/// Minutes to mute for when asked for `minutes`, at least one.
+fn mute_minutes(minutes: Option<i64>) -> u64 {
+    minutes.map_or(DEFAULT_MUTE_MINUTES, |minutes| minutes.max(1) as u64)
+}
+
+/// The last `count` announcements as a JSON attachment, oldest first.
+fn export(command: &CommandInteraction) -> (String, Option<CreateAttachment>) {
+    let count = command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == "count")
+        .and_then(|option| option.value.as_i64())
+        .map_or(DEFAULT_EXPORT_COUNT, |count| count.max(1) as usize);
+    let (content, json) = export_history(&ANNOUNCEMENT_HISTORY.lock().unwrap(), count);
+    (
+        content,
+        json.map(|json| CreateAttachment::bytes(json, "announcements.json")),
+    )
+}
+
+/// The reply and JSON file for the last `count` records of `history`.
+fn export_history(
+    history: &VecDeque<AnnouncementRecord>,
+    count: usize,
+) -> (String, Option<Vec<u8>>) {
+    let records: Vec<&AnnouncementRecord> = history
+        .iter()
+        .skip(history.len().saturating_sub(count))
+        .collect();
+    if records.is_empty() {
+        return ("Nothing announced yet.".to_string(), None);
+    }
+    let json = match serde_json::to_vec_pretty(&records) {
+        Ok(json) => json,
+        Err(err) => return (format!("Couldn't serialize the history: {err}"), None),
+    };
+    let content = if records.len() < count {
+        format!(
+            "Only {} announcements recorded, exporting all of them.",
+            records.len()
+        )
+    } else {
+        format!("Exported the last {count} announcements.")
+    };
+    (content, Some(json))
+}
+
+fn unmute() -> String {
+    unmute_reply(MUTED_UNTIL.lock().unwrap().take(), Instant::now()).to_string()
+}
+
+fn unmute_reply(muted_until: Option<Instant>, now: Instant) -> &'static str {
+    match muted_until {
+        Some(until) if now < until => "Unmuted.",
+        _ => "Wasn't muted.",
+    }
+}
+
+fn set_streak(command: &CommandInteraction) -> String {
+    let Some(streak) = command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == "streak")
+        .and_then(|option| option.value.as_i64())
+    else {
+        return "Missing streak.".to_string();
+    };
+    update_state(|state| state.streak = streak);
+    format!("Streak set to {streak}.")
+}
+
+fn set_result(command: &CommandInteraction) -> String {
+    let win = match string_option(command, "result") {
+        Some("win") => true,
+        Some("loss") => false,
+        _ => return "Result must be win or loss.".to_string(),
+    };
+    let streak = update_state(|state| {
+        state.record_result(win);
+        state.streak
+    });
+    format!(
+        "Last result set to {}, streak is now {streak}.",
+        if win { "win" } else { "loss" }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serenity::all::ChannelId;
+    use serenity::async_trait;
+
+    use super::*;
+    use crate::notifier::{ DiscordNotifier, RecordingSink, SinkCall };
+
+    #[test]
+    fn secrets_are_redacted() {
+        std::env::set_var("DOTAWATCHER_TEST_TOKEN", "hunter2");
+        assert_eq!(secret_line("DOTAWATCHER_TEST_TOKEN"), "DOTAWATCHER_TEST_TOKEN = <redacted>");
+        std::env::set_var("DOTAWATCHER_TEST_KEY_FILE", "/run/secrets/hunter2");
+        assert_eq!(secret_line("DOTAWATCHER_TEST_KEY"), "DOTAWATCHER_TEST_KEY = <redacted>");
+        std::env::set_var("DOTAWATCHER_TEST_EMPTY", "");
+        assert_eq!(secret_line("DOTAWATCHER_TEST_EMPTY"), "DOTAWATCHER_TEST_EMPTY = <unset>");
+        assert_eq!(secret_line("DOTAWATCHER_TEST_MISSING"), "DOTAWATCHER_TEST_MISSING = <unset>");
+    }
+
+    #[test]
+    fn mute_holds_back_announcements_until_it_runs_out() {
+        assert_eq!(mute_minutes(None), DEFAULT_MUTE_MINUTES);
+        assert_eq!(mute_minutes(Some(0)), 1);
+        assert_eq!(mute_minutes(Some(30)), 30);
+
+        let now = Instant::now();
+        let until = now + Duration::from_secs(mute_minutes(Some(30)) * 60);
+        assert!(crate::paused(false, Some(until), now));
+        assert!(!crate::paused(false, Some(until), until + Duration::from_secs(60)));
+
+        assert_eq!(unmute_reply(Some(until), now), "Unmuted.");
+        assert_eq!(unmute_reply(Some(until), until), "Wasn't muted.");
+        assert_eq!(unmute_reply(None, now), "Wasn't muted.");
+    }
+
+
+    #[test]
+    fn preview_renders_every_event_kind() {
+        crate::init_test_globals();
+        for kind in EventKind::ALL {
+            let samples = preview_samples(kind, 2);
+            assert!(!samples.is_empty(), "no {} samples", kind.key());
+            assert!(samples.iter().all(|sample| !sample.trim().is_empty()));
+        }
+        let preview = preview();
+        for kind in EventKind::ALL {
+            assert!(preview.contains(&format!("**{}**: ", kind.key())));
+        }
+    }
+
+
+    #[test]
+    fn export_attaches_the_most_recent_records() {
+        let record = |text: &str, sent_at| AnnouncementRecord {
+            kind: "match",
+            text: text.to_string(),
+            sent_at,
+        };
+        let history = VecDeque::from([record("first", 1), record("second", 2), record("third", 3)]);
+
+        let (content, json) = export_history(&history, 2);
+        assert_eq!(content, "Exported the last 2 announcements.");
+        let exported: serde_json::Value = serde_json::from_slice(&json.unwrap()).unwrap();
+        assert_eq!(
+            exported,
+            serde_json::json!([
+                { "kind": "match", "text": "second", "sent_at": 2 },
+                { "kind": "match", "text": "third", "sent_at": 3 },
+            ])
+        );
+
+        let (content, json) = export_history(&history, 5);
+        assert_eq!(content, "Only 3 announcements recorded, exporting all of them.");
+        let exported: Vec<serde_json::Value> = serde_json::from_slice(&json.unwrap()).unwrap();
+        assert_eq!(exported.len(), 3);
+
+        let (content, json) = export_history(&VecDeque::new(), 5);
+        assert_eq!(content, "Nothing announced yet.");
+        assert!(json.is_none());
+    }
+
+
+    #[tokio::test]
+    async fn refresh_data_reports_the_loaded_heroes() {
+        let body = r#"[
+            { "id": 1, "localized_name": "Anti-Mage", "primary_attr": "agi" },
+            { "id": 2, "localized_name": "Axe", "primary_attr": "str" }
+        ]"#;
+        let refresh = async { crate::parse_heroes(body).map(|heroes| heroes.len()) };
+        assert_eq!(refresh_data_with(refresh).await, "Loaded 2 heroes.");
+
+        let reply = refresh_data_with(async { Err(FetchError::RateLimited) }).await;
+        assert_eq!(reply, "Refreshing heroes failed, keeping the current list: rate limited");
+    }
+
+
+    struct FailingNotifier;
+
+    #[async_trait]
+    impl Notifier for FailingNotifier {
+        async fn send(&self, _announcement: &Announcement) -> anyhow::Result<()> {
+            Err(anyhow::anyhow!("missing permissions"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_command_posts_to_the_configured_channel() {
+        crate::init_test_globals();
+        let sink = Arc::new(RecordingSink::default());
+        let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(DiscordNotifier::new(sink.clone()))];
+        let reply = send_test(EventKind::Lifecycle, &notifiers).await;
+        assert_eq!(reply, "Sent a test lifecycle announcement to <#3>.");
+
+        let calls = sink.calls();
+        let [SinkCall::Send { channel, message }] = calls.as_slice() else {
+            panic!("expected one send, got {calls:?}");
+        };
+        assert_eq!(*channel, ChannelId::new(3));
+        assert_eq!(
+            message["content"],
+            "Test lifecycle announcement from /test, the bot can post here."
+        );
+
+        let notifiers: Vec<Box<dyn Notifier>> = vec![Box::new(FailingNotifier)];
+        let reply = send_test(EventKind::Lifecycle, &notifiers).await;
+        assert_eq!(reply, "Test lifecycle announcement failed: missing permissions");
+    }
+}
@@ -0,0 +1,215 @@
+use std::sync::Mutex;
+
+use serenity::all::Activity;
+use tokio::time::{ Duration, Instant };
+
+use crate::{ render_template, target_name, FEATURES, LOCALIZATION };
+
+pub const DOTA_ACTIVITY_NAME: &str = "Dota 2";
+
+/// Searching again this soon after leaving the queue without a match isn't announced.
+const REQUEUE_GRACE: Duration = Duration::from_secs(120);
+
+/// Last rich presence state seen for Dota, used to announce each transition once.
+static LAST_DOTA_PRESENCE: Mutex<Option<DotaPresence>> = Mutex::new(None);
+/// When the target last left the queue without finding a match.
+static LEFT_QUEUE_AT: Mutex<Option<Instant>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Radiant,
+    Dire,
+}
+
+/// The parts of Dota's rich presence worth announcing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DotaPresence {
+    Menu,
+    Searching,
+    InMatch(Option<Side>),
+}
+
+pub fn is_dota(activity: &Activity) -> bool {
+    activity.name == DOTA_ACTIVITY_NAME
+}
+
+/// Reads the Dota state out of the activity's `details`/`state` texts.
+pub fn parse(activity: &Activity) -> Option<DotaPresence> {
+    let text = [activity.details.as_deref(), activity.state.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+
+    if text.contains("finding a match") || text.contains("searching") || text.contains("in queue") {
+        Some(DotaPresence::Searching)
+    } else if text.contains("radiant") {
+        Some(DotaPresence::InMatch(Some(Side::Radiant)))
+    } else if text.contains("dire") {
+        Some(DotaPresence::InMatch(Some(Side::Dire)))
+    } else if text.contains("playing") {
+        Some(DotaPresence::InMatch(None))
+    } else if text.contains("main menu") {
+        Some(DotaPresence::Menu)
+    } else {
+        None
+    }
+}
+
+/// Records `presence` and, if it differs from the previous one, returns
+/// the previous presence along with it.
+pub fn transition(presence: Option<DotaPresence>) -> Option<(Option<DotaPresence>, DotaPresence)> {
+    record_transition(
+        &mut LAST_DOTA_PRESENCE.lock().unwrap(),
+        &mut LEFT_QUEUE_AT.lock().unwrap(),
+        presence,
+        Instant::now(),
+    )
+}
+
+fn record_transition(
+    last: &mut Option<DotaPresence>,
+    left_queue_at: &mut Option<Instant>,
+    presence: Option<DotaPresence>,
+    now: Instant,
+) -> Option<(Option<DotaPresence>, DotaPresence)> {
+    if *last == presence {
+        return None;
+    }
+    let previous = std::mem::replace(last, presence);
+    let found_match = matches!(presence, Some(DotaPresence::InMatch(_)));
+    if previous == Some(DotaPresence::Searching) && !found_match {
+        *left_queue_at = Some(now);
+    }
+    presence.map(|presence| (previous, presence))
+}
+
+/// The lines to post for a transition from `previous` to `presence`. With
+/// `queue_announcements`, joining the queue and finding a match get lines of
+/// their own, and requeueing right after leaving the queue stays quiet.
+pub fn announcements(previous: Option<DotaPresence>, presence: DotaPresence) -> Vec<String> {
+    if !FEATURES.get().unwrap().queue_announcements {
+        return format(presence).into_iter().collect();
+    }
+    let left_queue_at = *LEFT_QUEUE_AT.lock().unwrap();
+    queue_announcements(previous, presence, left_queue_at, Instant::now())
+}
+
+fn queue_announcements(
+    previous: Option<DotaPresence>,
+    presence: DotaPresence,
+    left_queue_at: Option<Instant>,
+    now: Instant,
+) -> Vec<String> {
+    let locals = LOCALIZATION.get().unwrap();
+    match (previous, presence) {
+        (_, DotaPresence::Searching) => {
+            let requeue =
+                left_queue_at.is_some_and(|left| now.duration_since(left) < REQUEUE_GRACE);
+            if requeue {
+                return Vec::new();
+            }
+            let template = locals.dota_searching.as_deref();
+            vec![render(template.unwrap_or("{target_name} is now searching for a match"))]
+        }
+        (Some(DotaPresence::Searching), DotaPresence::InMatch(_)) => {
+            let template = locals.dota_match_found.as_deref();
+            let mut lines = vec![render(template.unwrap_or("{target_name} found a match"))];
+            lines.extend(format(presence));
+            lines
+        }
+        _ => format(presence).into_iter().collect(),
+    }
+}
+
+fn render(template: &str) -> String {
+    render_template(template, &[("target_name", &target_name())])
+}
+
+/// Renders the localized line for a transition, if a template is configured.
+pub fn format(presence: DotaPresence) -> Option<String> {
+    let locals = LOCALIZATION.get().unwrap();
+    let template = match presence {
+        DotaPresence::Menu => locals.dota_menu.as_deref(),
+        DotaPresence::Searching => locals.dota_searching.as_deref(),
+        DotaPresence::InMatch(_) => locals.dota_in_match.as_deref(),
+    }?;
+    let side = match presence {
+        DotaPresence::InMatch(Some(Side::Radiant)) => {
+            locals.radiant.as_deref().unwrap_or("Radiant")
+        }
+        DotaPresence::InMatch(Some(Side::Dire)) => locals.dire.as_deref().unwrap_or("Dire"),
+        _ => "",
+    };
+    Some(
+        render_template(
+            template,
+            &[("target_name", &target_name()), ("side", side)],
+        )
+        .trim()
+        .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn presence(details: &str) -> Option<DotaPresence> {
+        let activity = serde_json::json!({
+            "name": DOTA_ACTIVITY_NAME,
+            "type": 0,
+            "created_at": 0,
+            "details": details,
+        });
+        parse(&serde_json::from_value(activity).unwrap())
+    }
+
+    #[test]
+    fn rich_presence_texts_map_to_states() {
+        assert_eq!(presence("Main Menu"), Some(DotaPresence::Menu));
+        assert_eq!(presence("Finding a Match"), Some(DotaPresence::Searching));
+        assert_eq!(
+            presence("Ranked: Axe - Radiant"),
+            Some(DotaPresence::InMatch(Some(Side::Radiant)))
+        );
+        assert_eq!(presence("Playing as Axe"), Some(DotaPresence::InMatch(None)));
+        assert_eq!(presence("Watching a replay"), None);
+    }
+
+    #[test]
+    fn queueing_and_finding_a_match_are_announced_once() {
+        crate::init_test_globals();
+        let start = Instant::now();
+        let mut last = None;
+        let mut left_queue_at = None;
+        let mut step = |presence, secs| {
+            let now = start + Duration::from_secs(secs);
+            let (previous, presence) =
+                record_transition(&mut last, &mut left_queue_at, Some(presence), now)?;
+            Some(queue_announcements(previous, presence, left_queue_at, now))
+        };
+        let radiant = DotaPresence::InMatch(Some(Side::Radiant));
+
+        assert_eq!(step(DotaPresence::Menu, 0), Some(Vec::new()));
+        assert_eq!(
+            step(DotaPresence::Searching, 10),
+            Some(vec!["Target is now searching for a match".to_string()])
+        );
+        assert_eq!(step(DotaPresence::Searching, 20), None);
+        assert_eq!(step(radiant, 30), Some(vec!["Target found a match".to_string()]));
+        assert_eq!(step(radiant, 40), None);
+
+        // Leaving the queue and searching again right away stays quiet.
+        assert_eq!(step(DotaPresence::Menu, 3000), Some(Vec::new()));
+        assert_eq!(step(DotaPresence::Searching, 3010).unwrap().len(), 1);
+        assert_eq!(step(DotaPresence::Menu, 3020), Some(Vec::new()));
+        assert_eq!(step(DotaPresence::Searching, 3030), Some(Vec::new()));
+        assert_eq!(step(DotaPresence::Menu, 3040), Some(Vec::new()));
+        assert_eq!(
+            step(DotaPresence::Searching, 3040 + REQUEUE_GRACE.as_secs()),
+            Some(vec!["Target is now searching for a match".to_string()])
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use std::string::FromUtf8Error;
+
+use reqwest::StatusCode;
+use thiserror::Error;
+
+/// Why a request to an external API failed, so callers can react to the kind
+/// of failure rather than to its message.
+#[derive(Debug, Error)]
+pub enum FetchError {
+    /// Connecting, sending or reading the response failed.
+    #[error("network error: {0}")]
+    Network(#[source] reqwest::Error),
+    /// The API answered 429 Too Many Requests.
+    #[error("rate limited")]
+    RateLimited,
+    /// The response body wasn't what we expected.
+    #[error("unexpected response: {0}")]
+    Decode(String),
+    #[error("not found")]
+    NotFound,
+    /// Any other unsuccessful response, such as a 5xx or an oversized body.
+    #[error("API error: {0}")]
+    Api(String),
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(err: reqwest::Error) -> Self {
+        match err.status() {
+            Some(StatusCode::TOO_MANY_REQUESTS) => FetchError::RateLimited,
+            Some(StatusCode::NOT_FOUND) => FetchError::NotFound,
+            Some(status) => FetchError::Api(status.to_string()),
+            None if err.is_decode() => FetchError::Decode(err.to_string()),
+            None => FetchError::Network(err),
+        }
+    }
+}
+
+impl From<serde_json::Error> for FetchError {
+    fn from(err: serde_json::Error) -> Self {
+        FetchError::Decode(err.to_string())
+    }
+}
+
+impl From<FromUtf8Error> for FetchError {
+    fn from(err: FromUtf8Error) -> Self {
+        FetchError::Decode(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undecodable_bodies_are_decode_errors() {
+        let err = serde_json::from_str::<u64>("not json").unwrap_err();
+        assert!(matches!(FetchError::from(err), FetchError::Decode(_)));
+
+        let err = String::from_utf8(vec![0xff]).unwrap_err();
+        assert!(matches!(FetchError::from(err), FetchError::Decode(_)));
+    }
+
+    #[test]
+    fn messages_name_the_kind_of_failure() {
+        assert_eq!(FetchError::RateLimited.to_string(), "rate limited");
+        let err = FetchError::Api("502 Bad Gateway".to_string());
+        assert_eq!(err.to_string(), "API error: 502 Bad Gateway");
+    }
+}
@@ -0,0 +1,113 @@
+use std::env;
+
+use anyhow::{ anyhow, Result };
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+
+/// Boolean switches for optional behavior, read from `features.json` and
+/// overridable per flag with `FEATURE_<NAME>=true|false` environment variables.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Features {
+    /// Collapse presence announcements into a single line.
+    pub compact_messages: bool,
+    /// Hold presence announcements until the channel is quiet instead of dropping TTS,
+    /// for up to five minutes before posting them without TTS.
+    pub active_channel_delay: bool,
+    /// With `ALLOWED_ACTIVITY_APP_IDS` set, still announce activities that have
+    /// no application id, such as custom statuses.
+    pub allow_activities_without_app_id: bool,
+    /// Announce Dota rich presence transitions instead of raw Dota activity updates.
+    pub dota_rich_presence: bool,
+    /// Name the target's party members, at the cost of fetching each full match.
+    pub party_members: bool,
+    /// Never post on its own; keep polling so commands can still report and replay.
+    pub passive_mode: bool,
+    /// Announce games added to the target's Steam library. Needs
+    /// `STEAM_API_KEY` and a public game list.
+    pub steam_library: bool,
+    /// List both teams' heroes under match announcements, fetching each full match.
+    pub team_heroes: bool,
+    /// Sum up the matches played when the target goes offline.
+    pub logoff_summary: bool,
+    /// Announce custom status text changes on their own line instead of as an activity.
+    pub custom_status_announcements: bool,
+    /// On a fresh state file, announce the latest match once instead of only recording it.
+    pub seed_announce_last_match: bool,
+    /// Find the reaction emoji's id by its name in the target guild on connect.
+    pub emoji_by_name: bool,
+    /// Show gold amounts such as net worth as `18.4k` instead of `18432`.
+    pub abbreviate_numbers: bool,
+    /// Post a message when the bot first connects and when it shuts down.
+    pub announce_lifecycle: bool,
+    /// Register admin commands that tamper with persisted state for demos.
+    pub debug_commands: bool,
+    /// Post one message per stream and edit it on title changes instead of
+    /// announcing every streaming presence update.
+    pub stream_updates: bool,
+    /// Announce Steam achievements unlocked while playing a game listed in
+    /// `STEAM_APP_IDS`. Needs `STEAM_API_KEY`.
+    pub steam_achievements: bool,
+    /// Post the score of the target's match while it's live, when OpenDota lists it.
+    pub live_scores: bool,
+    /// Ignore Discord presence entirely and run without the privileged
+    /// presence and member intents, e.g. for match-only setups.
+    pub disable_presence: bool,
+    /// Don't react to the target's messages, dropping the message intents
+    /// unless presence still needs them for `ACTIVE_CHANNEL_WINDOW_SECS`.
+    pub disable_reactions: bool,
+    /// With `dota_rich_presence`, announce joining the Dota queue and finding a
+    /// match even without templates, staying quiet on quick requeues.
+    pub queue_announcements: bool,
+    /// Prefer `localization.<locale>.json` matching the guild's preferred locale.
+    pub guild_locale: bool,
+    /// Only post about ranked matchmaking games; others are recorded silently.
+    pub ranked_only: bool,
+}
+
+impl Features {
+    /// Parses `file` (if present) and applies environment overrides on top.
+    pub fn load(file: Option<&str>) -> Result<Self> {
+        let mut value = match file {
+            Some(body) => serde_json::from_str(body)?,
+            None => serde_json::to_value(Features::default())?,
+        };
+        let Value::Object(flags) = &mut value else {
+            return Err(anyhow!("features must be a JSON object"));
+        };
+
+        let known = serde_json::to_value(Features::default())?;
+        for name in known.as_object().unwrap().keys() {
+            let var = format!("FEATURE_{}", name.to_uppercase());
+            if let Ok(override_value) = env::var(&var) {
+                let enabled: bool = override_value
+                    .parse()
+                    .map_err(|_| anyhow!("{var} not true or false"))?;
+                flags.insert(name.clone(), enabled.into());
+            }
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let file = r#"{ "team_heroes": true, "live_scores": true }"#;
+        env::set_var("FEATURE_LIVE_SCORES", "maybe");
+        let err = Features::load(Some(file)).unwrap_err();
+        assert_eq!(err.to_string(), "FEATURE_LIVE_SCORES not true or false");
+
+        env::set_var("FEATURE_LIVE_SCORES", "false");
+        let features = Features::load(Some(file)).unwrap();
+        env::remove_var("FEATURE_LIVE_SCORES");
+        assert!(features.team_heroes);
+        assert!(!features.live_scores);
+        assert!(!features.passive_mode);
+
+        assert!(Features::load(Some("[]")).is_err());
+    }
+}
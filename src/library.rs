@@ -0,0 +1,97 @@
+use std::collections::BTreeSet;
+
+use serde::Deserialize;
+use tokio::time::{ self, Duration };
+
+use crate::error::FetchError;
+use crate::notifier::Announcement;
+use crate::state::OwnedGames;
+use crate::steam::{ steam_get_json, STEAM64_BASE };
+use crate::{
+    announce, render_template, sanitize_activity_text, target_name, EventKind, FEATURES,
+    LIBRARY_FILE, LOCALIZATION, STEAM_API_KEY, TARGET_STEAMID32,
+};
+
+const STEAM_OWNED_GAMES_URL: &str =
+    "https://api.steampowered.com/IPlayerService/GetOwnedGames/v1/";
+/// Libraries change far less often than profiles, so they're checked less often.
+const LIBRARY_POLL_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Deserialize)]
+struct OwnedGamesResponse {
+    response: Library,
+}
+
+#[derive(Debug, Deserialize)]
+struct Library {
+    /// Missing when the target's game list is private.
+    #[serde(default)]
+    games: Option<Vec<OwnedGame>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OwnedGame {
+    appid: u32,
+    /// Present because the request asks for app info.
+    #[serde(default)]
+    name: Option<String>,
+}
+
+/// The target's games, or `None` if their game list is private.
+async fn request_owned_games() -> Result<Option<Vec<OwnedGame>>, FetchError> {
+    let steam_id = STEAM64_BASE + TARGET_STEAMID32.get().unwrap();
+    let url = format!(
+        "{STEAM_OWNED_GAMES_URL}?steamid={steam_id}&include_appinfo=1&include_played_free_games=1"
+    );
+    let response: OwnedGamesResponse = steam_get_json(&url).await?;
+    Ok(response.response.games)
+}
+
+/// Announces games added to the target's Steam library. The first check only
+/// records what they own.
+pub async fn library_loop() {
+    if STEAM_API_KEY.get().unwrap().is_empty() {
+        if FEATURES.get().unwrap().steam_library {
+            eprintln!("steam_library needs STEAM_API_KEY, library polling disabled");
+        }
+        return;
+    }
+    let path = LIBRARY_FILE.get().unwrap();
+    let mut owned = OwnedGames::load(path);
+
+    let mut interval = time::interval(LIBRARY_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if !FEATURES.get().unwrap().steam_library {
+            continue;
+        }
+
+        let games = match request_owned_games().await {
+            // A private list would look like every game was removed.
+            Ok(Some(games)) => games,
+            Ok(None) => continue,
+            Err(err) => {
+                eprintln!("Couldn't fetch the target's Steam library: {err}");
+                continue;
+            }
+        };
+        let new = owned.update(games.iter().map(|game| game.appid).collect::<BTreeSet<_>>());
+        if let Err(err) = owned.save(path) {
+            eprintln!("Couldn't save owned games to {}: {err}", path.display());
+        }
+
+        let template = LOCALIZATION.get().unwrap().game_added.as_deref();
+        for app_id in new {
+            let name = games
+                .iter()
+                .find(|game| game.appid == app_id)
+                .and_then(|game| game.name.as_deref())
+                .map_or_else(|| app_id.to_string(), sanitize_activity_text);
+            let content = render_template(
+                template.unwrap_or("{target_name} just added {game} to their library"),
+                &[("target_name", &target_name()), ("game", &name)],
+            );
+            announce(Announcement::new(EventKind::Presence, content)).await;
+        }
+    }
+}
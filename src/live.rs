@@ -0,0 +1,134 @@
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::time::{ self, Duration };
+
+use crate::error::FetchError;
+use crate::notifier::Announcement;
+use crate::{
+    announce, dota_presence, opendota_get, opendota_url, render_template, session, target_name,
+    EventKind, FEATURES, LIVE_SCORE_INTERVAL_SECS, LIVE_SCORE_MIN_CHANGE, LOCALIZATION,
+    TARGET_STEAMID32,
+};
+
+/// A game from OpenDota's `/live` listing, which only covers notable ongoing
+/// matches, so not every game of the target shows up.
+#[derive(Debug, Deserialize)]
+struct LiveGame {
+    /// Sent as a string or a number depending on the game; only compared.
+    match_id: Value,
+    #[serde(default)]
+    game_time: i64,
+    #[serde(default)]
+    radiant_score: i64,
+    #[serde(default)]
+    dire_score: i64,
+    #[serde(default)]
+    players: Vec<LivePlayer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LivePlayer {
+    #[serde(default)]
+    account_id: Option<u64>,
+}
+
+/// The score last posted for a live match.
+#[derive(Debug, Clone, PartialEq)]
+struct PostedScore {
+    match_id: Value,
+    radiant: i64,
+    dire: i64,
+}
+
+/// Whether `game` moved far enough from the last posted score to be worth a
+/// post. A different match always is.
+fn score_changed(posted: Option<&PostedScore>, game: &LiveGame, min_change: i64) -> bool {
+    match posted {
+        Some(posted) if posted.match_id == game.match_id => {
+            let change = (game.radiant_score - posted.radiant).abs()
+                + (game.dire_score - posted.dire).abs();
+            change >= min_change
+        }
+        _ => true,
+    }
+}
+
+async fn request_live_game(account_id: u64) -> Result<Option<LiveGame>, FetchError> {
+    let body = opendota_get(&opendota_url("live")).await?;
+    let games: Vec<LiveGame> = serde_json::from_str(&body)?;
+    Ok(games.into_iter().find(|game| {
+        game.players
+            .iter()
+            .any(|player| player.account_id == Some(account_id))
+    }))
+}
+
+/// Posts the score of the target's live match every `LIVE_SCORE_INTERVAL_SECS`
+/// while they play Dota, skipping polls where fewer than
+/// `LIVE_SCORE_MIN_CHANGE` kills happened since the last post.
+pub async fn live_score_loop() {
+    let account_id = *TARGET_STEAMID32.get().unwrap();
+    let min_change = *LIVE_SCORE_MIN_CHANGE.get().unwrap();
+    let period = Duration::from_secs(*LIVE_SCORE_INTERVAL_SECS.get().unwrap());
+    let mut interval = time::interval(period);
+    let mut posted: Option<PostedScore> = None;
+    loop {
+        interval.tick().await;
+        let playing_dota = session::current_game()
+            .is_some_and(|game| game == dota_presence::DOTA_ACTIVITY_NAME);
+        if !FEATURES.get().unwrap().live_scores || !playing_dota {
+            posted = None;
+            continue;
+        }
+
+        let game = match request_live_game(account_id).await {
+            Ok(Some(game)) => game,
+            Ok(None) => continue,
+            Err(err) => {
+                eprintln!("Couldn't fetch live games: {err}");
+                continue;
+            }
+        };
+        if !score_changed(posted.as_ref(), &game, min_change) {
+            continue;
+        }
+        posted = Some(PostedScore {
+            match_id: game.match_id.clone(),
+            radiant: game.radiant_score,
+            dire: game.dire_score,
+        });
+
+        let template = LOCALIZATION.get().unwrap().live_score.as_deref();
+        let content = render_template(
+            template.unwrap_or("Radiant {radiant_score} - {dire_score} Dire, {minutes} min in"),
+            &[
+                ("target_name", &target_name()),
+                ("radiant_score", &game.radiant_score.to_string()),
+                ("dire_score", &game.dire_score.to_string()),
+                ("minutes", &(game.game_time.max(0) / 60).to_string()),
+            ],
+        );
+        announce(Announcement::new(EventKind::Match, content)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn game(match_id: Value, radiant_score: i64, dire_score: i64) -> LiveGame {
+        LiveGame { match_id, game_time: 900, radiant_score, dire_score, players: Vec::new() }
+    }
+
+    #[test]
+    fn scores_are_posted_again_only_after_enough_kills() {
+        let posted = PostedScore { match_id: json!("7"), radiant: 10, dire: 8 };
+        assert!(!score_changed(Some(&posted), &game(json!("7"), 10, 8), 3));
+        assert!(!score_changed(Some(&posted), &game(json!("7"), 11, 9), 3));
+        assert!(score_changed(Some(&posted), &game(json!("7"), 12, 9), 3));
+        assert!(score_changed(Some(&posted), &game(json!("8"), 10, 8), 3));
+        assert!(score_changed(None, &game(json!(7), 0, 0), 3));
+    }
+}
@@ -1,19 +1,27 @@
-use std::collections::HashMap;
+use std::collections::{ BTreeMap, HashMap, VecDeque };
+use std::hash::{ BuildHasher, RandomState };
 use std::env;
-use std::sync::OnceLock;
+use std::future::Future;
+use std::num::NonZeroU64;
+use std::path::{ Path, PathBuf };
+use std::str::FromStr;
+use std::sync::{ Arc, Mutex as StdMutex, OnceLock };
+use std::time::SystemTime;
 
 use serenity::all::{
-    ActivityData, ActivityType, CacheHttp, ChannelId, Client, Context, CreateMessage, EmojiId,
-    EventHandler, GatewayIntents, GuildId, Message, OnlineStatus, Presence, ReactionType, Ready,
-    UserId,
+    Activity, ActivityData, ActivityType, ChannelId, Client, Colour, Context, Emoji, EmojiId,
+    EventHandler, GatewayIntents, GuildId, Http, HttpError, Interaction, Member, Message,
+    MessageId, OnlineStatus, Presence, ReactionType, Ready, ResumedEvent, RoleId, User, UserId,
 };
 use serenity::async_trait;
 
 use tokio::sync::Mutex;
-use tokio::time::{ self, Duration };
+use tokio::task::JoinHandle;
+use tokio::time::{ self, Duration, Instant };
 
 use anyhow::{ anyhow, Result };
-use serde::Deserialize;
+use serde::{ Deserialize, Deserializer, Serialize };
+use unicode_segmentation::UnicodeSegmentation;
 
 macro_rules! get_string_for_status {
     ($status:expr) => {
@@ -41,37 +49,454 @@ macro_rules! set_env_num {
     };
 }
 
-macro_rules! set_env_str {
+macro_rules! set_env_num_or_default {
+    ($var:expr, $default:expr) => {
+        let var_str = stringify!($var);
+        $var.set(env::var(var_str).map_or($default, |value| {
+            value.parse().expect("{var_str} not a number")
+        }))
+        .unwrap();
+    };
+}
+
+macro_rules! set_env_num_list_or_default {
     ($var:expr) => {
         let var_str = stringify!($var);
-        $var.set(env::var(var_str).expect("Expected {var_str} in the environment"))
+        $var.set(env::var(var_str).map_or(Vec::new(), |value| {
+            value
+                .split(',')
+                .filter(|item| !item.trim().is_empty())
+                .map(|item| item.trim().parse().expect("{var_str} not a list of numbers"))
+                .collect()
+        }))
+        .unwrap();
+    };
+}
+
+macro_rules! set_env_parse_or_default {
+    ($var:expr, $default:expr) => {
+        let var_str = stringify!($var);
+        $var.set(env::var(var_str).map_or($default, |value| {
+            value
+                .parse()
+                .unwrap_or_else(|err| panic!("Invalid {var_str}: {err}"))
+        }))
+        .unwrap();
+    };
+}
+
+macro_rules! set_env_str_or_default {
+    ($var:expr) => {
+        $var.set(env::var(stringify!($var)).unwrap_or_default())
             .unwrap();
     };
 }
 
+mod achievements;
+mod commands;
+mod dota_presence;
+mod error;
+mod features;
+mod library;
+mod live;
+mod notifier;
+mod ratelimit;
+mod reload;
+mod session;
+mod source;
+mod state;
+mod steam;
+mod steam_group;
+mod steam_profile;
+mod stream;
+
+use achievements::SteamApps;
+use error::FetchError;
+use features::Features;
+use notifier::{ Announcement, DiscordNotifier, EmbedData, HttpSink, MessageSink, Notifier };
+use ratelimit::RateLimiter;
+use reload::Reloadable;
+use source::{ DotaSource, MatchSource, OpenDotaSource, ValveSource };
+use state::{ KdaRecord, LeaderboardChange, NewRecord, PersistentState, SeenMatches };
+use steam::SteamKeys;
+
 static TARGET_GUILD: OnceLock<u64> = OnceLock::new();
-static OUTPUT_CHANNEL: OnceLock<u64> = OnceLock::new();
+static OUTPUT_CHANNEL: OnceLock<Vec<u64>> = OnceLock::new();
 static TARGET_USER: OnceLock<u64> = OnceLock::new();
 static TARGET_STEAMID32: OnceLock<u64> = OnceLock::new();
-static EMOJI_ID: OnceLock<u64> = OnceLock::new();
-static EMOJI_NAME: OnceLock<String> = OnceLock::new();
-static LOCALIZATION: OnceLock<Localization> = OnceLock::new();
+/// The target's other accounts, polled alongside `TARGET_STEAMID32`.
+static ALT_ACCOUNTS: OnceLock<AltAccounts> = OnceLock::new();
+/// Discord users to mention when their Steam account shares the target's team.
+static TEAMMATE_USERS: OnceLock<TeammateUsers> = OnceLock::new();
+/// Reaction added to the target's messages.
+static REACTION: OnceLock<ReactionType> = OnceLock::new();
+/// `REACTION` with its id looked up by name in the target guild, see `resolve_reaction`.
+static RESOLVED_REACTION: StdMutex<Option<ReactionType>> = StdMutex::new(None);
+/// Used instead once `REACTION` keeps failing, e.g. because the emoji was deleted.
+static FALLBACK_REACTION: OnceLock<Option<ReactionType>> = OnceLock::new();
+/// Reactions that failed in a row with the reaction currently in use.
+static REACTION_FAILURES: StdMutex<u32> = StdMutex::new(0);
+static LOCALIZATION: Reloadable<Localization> = Reloadable::new();
+static MATCH_PREFIX: OnceLock<String> = OnceLock::new();
+static PRESENCE_PREFIX: OnceLock<String> = OnceLock::new();
+/// Keys for the Steam Web API, shared by every Steam watcher and `MATCH_SOURCE=valve`.
+static STEAM_API_KEY: OnceLock<SteamKeys> = OnceLock::new();
+/// Carries its own credentials in most setups, so it's read like the other secrets.
+static EXTERNAL_WEBHOOK_URL: OnceLock<String> = OnceLock::new();
+static ADMIN_USERS: OnceLock<Vec<u64>> = OnceLock::new();
+static MAX_MESSAGE_LENGTH: OnceLock<usize> = OnceLock::new();
+static STOMP_MAX_MINUTES: OnceLock<i64> = OnceLock::new();
+static HEARTBREAKER_MIN_MINUTES: OnceLock<i64> = OnceLock::new();
+static HIGH_KILLS: OnceLock<i64> = OnceLock::new();
+static FIRST_BLOOD_MAX_SECS: OnceLock<i64> = OnceLock::new();
+static HERO_DUST_DAYS: OnceLock<i64> = OnceLock::new();
+static FEATURES: Reloadable<Features> = Reloadable::new();
+static STATE_FILE: OnceLock<PathBuf> = OnceLock::new();
+static STATE: OnceLock<StdMutex<PersistentState>> = OnceLock::new();
+static SEEN_MATCHES_FILE: OnceLock<PathBuf> = OnceLock::new();
+static SEEN_MATCHES: OnceLock<StdMutex<SeenMatches>> = OnceLock::new();
+/// Where the target's owned Steam games are kept for `steam_library`.
+static LIBRARY_FILE: OnceLock<PathBuf> = OnceLock::new();
+static ACHIEVEMENTS_FILE: OnceLock<PathBuf> = OnceLock::new();
+static STEAM_APP_IDS: OnceLock<SteamApps> = OnceLock::new();
+/// Steam group whose members are watched as well; 0 watches none.
+static STEAM_GROUP_ID: OnceLock<u64> = OnceLock::new();
+/// Group members watched at most, to keep Steam API requests down.
+static STEAM_GROUP_MAX_MEMBERS: OnceLock<usize> = OnceLock::new();
+static LIVE_SCORE_INTERVAL_SECS: OnceLock<u64> = OnceLock::new();
+static LIVE_SCORE_MIN_CHANGE: OnceLock<i64> = OnceLock::new();
+static ACTIVE_CHANNEL_WINDOW_SECS: OnceLock<u64> = OnceLock::new();
+static MATCH_EMBED_COLORS: OnceLock<EmbedColorScheme> = OnceLock::new();
+static MATCH_SOURCE: OnceLock<MatchSource> = OnceLock::new();
+static KDA_STYLE: OnceLock<KdaStyle> = OnceLock::new();
+static OPENDOTA_BASE_URL: OnceLock<String> = OnceLock::new();
+static OPENDOTA_RATE_PER_MINUTE: OnceLock<u32> = OnceLock::new();
+static OPENDOTA_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+static ANNOUNCE_RESULTS: OnceLock<ResultFilter> = OnceLock::new();
+static LONG_SESSION_HOURS: OnceLock<Vec<u64>> = OnceLock::new();
+/// Match counts celebrated with the `match_milestone` line.
+static MATCH_MILESTONES: OnceLock<Vec<u64>> = OnceLock::new();
+static DOTA_IDLE_MINUTES: OnceLock<u64> = OnceLock::new();
+static FIRST_MATCH_GAP_HOURS: OnceLock<i64> = OnceLock::new();
+static MATCH_ANNOUNCE_DELAY_SECS: OnceLock<u64> = OnceLock::new();
+/// How many matches per online session and announcements for `/export` are
+/// remembered. Duplicate protection is sized separately by `MATCH_LRU_CAP`.
+static HISTORY_CAP: OnceLock<usize> = OnceLock::new();
+/// How many announced match ids are remembered to avoid posting a match twice.
+static MATCH_LRU_CAP: OnceLock<usize> = OnceLock::new();
+/// Announcements sent so far, oldest first.
+static ANNOUNCEMENT_HISTORY: StdMutex<VecDeque<AnnouncementRecord>> =
+    StdMutex::new(VecDeque::new());
+/// Above 1, matches are collected and announced together in batches of this size.
+static ANNOUNCE_EVERY_N_MATCHES: OnceLock<usize> = OnceLock::new();
+/// Matches waiting for their batch to fill up.
+static PENDING_BATCH: StdMutex<Vec<MatchData>> = StdMutex::new(Vec::new());
+/// Re-entering a game within this many seconds of it last being announced or
+/// left isn't announced again, e.g. when the game is relaunched after a crash.
+static GAME_REANNOUNCE_SECS: OnceLock<u64> = OnceLock::new();
+/// When each game was last announced or left, by activity name.
+static GAME_LAST_SEEN: StdMutex<BTreeMap<String, Instant>> = StdMutex::new(BTreeMap::new());
+static ALLOWED_ACTIVITY_APP_IDS: OnceLock<Vec<u64>> = OnceLock::new();
+static NOTIFIERS: OnceLock<Vec<Box<dyn Notifier>>> = OnceLock::new();
+static ROUTES: Reloadable<RoutingTable> = Reloadable::new();
 
-static HEROES: OnceLock<HashMap<i64, String>> = OnceLock::new();
+static HEROES: Reloadable<HashMap<i64, Hero>> = Reloadable::new();
+static CURRENT_STATE: StdMutex<Option<PlayerState>> = StdMutex::new(None);
+static LAST_ANNOUNCEMENTS: StdMutex<BTreeMap<EventKind, LastAnnouncement>> =
+    StdMutex::new(BTreeMap::new());
+static DOTA_POLL_STATS: StdMutex<PollStats> = StdMutex::new(PollStats::new());
+static LAST_HUMAN_MESSAGE: StdMutex<Option<Instant>> = StdMutex::new(None);
+static LAST_SENT: StdMutex<BTreeMap<EventKind, Instant>> = StdMutex::new(BTreeMap::new());
+static RANK_TIER: StdMutex<Option<i64>> = StdMutex::new(None);
+static LAST_CUSTOM_STATUS: StdMutex<Option<String>> = StdMutex::new(None);
+/// Automatic announcements are dropped until then, set by `/mute`.
+static MUTED_UNTIL: StdMutex<Option<Instant>> = StdMutex::new(None);
+/// Set while the target isn't a member of the guild, so no presence updates arrive.
+static TARGET_LEFT_GUILD: StdMutex<bool> = StdMutex::new(false);
+/// The target guild's preferred locale, such as `ru` or `en-US`, once known.
+static GUILD_LOCALE: StdMutex<Option<String>> = StdMutex::new(None);
+/// Matches finished since the target last came online, for the logoff summary.
+static ONLINE_SESSION_MATCHES: StdMutex<Vec<MatchData>> = StdMutex::new(Vec::new());
 
 const MAIN_LOOP_INTERVAL: Duration = Duration::from_secs(60);
+const DEFAULT_OPENDOTA_BASE_URL: &str = "https://api.opendota.com/api";
+const MAX_BODY_BYTES: usize = 8 * 1024 * 1024;
+const WEBHOOK_ATTEMPTS: u32 = 3;
+/// Polls a match may go without a result before it's skipped, about half an
+/// hour at the main loop's interval. Matches OpenDota never parses would
+/// otherwise hold up every later one.
+const RESULT_WAIT_POLLS: u32 = 30;
+/// How often the medal and leaderboard rank are refreshed between matches.
+const RANK_REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+/// Failed reactions in a row before `FALLBACK_REACTION` takes over.
+/// Only Discord's "Unknown Emoji" errors count.
+const REACTION_FAILURE_LIMIT: u32 = 3;
+/// Discord's JSON error code for a reaction with an emoji it doesn't know.
+const DISCORD_UNKNOWN_EMOJI: isize = 10014;
+/// Extra pause before polling again after a 429.
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(60);
 
-#[derive(Debug, Deserialize)]
+const RANK_MEDALS: [&str; 8] = [
+    "herald", "guardian", "crusader", "archon", "legend", "ancient", "divine", "immortal",
+];
+
+const GAME_MODE_TURBO: i64 = 23;
+const LOBBY_TYPE_RANKED: i64 = 7;
+
+const DISCORD_CONTENT_LIMIT: usize = 2000;
+const DISCORD_EMBED_DESCRIPTION_LIMIT: usize = 4096;
+const DISCORD_EMBED_AUTHOR_LIMIT: usize = 256;
+const DISCORD_THREAD_NAME_LIMIT: usize = 100;
+/// Keeps the team composition line from crowding out the rest of a match announcement.
+const TEAMS_LINE_LIMIT: usize = 400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum EventKind {
+    Match,
+    Presence,
+    /// The bot itself starting or shutting down.
+    Lifecycle,
+}
+
+impl EventKind {
+    const ALL: [EventKind; 3] = [EventKind::Match, EventKind::Presence, EventKind::Lifecycle];
+
+    /// Name used for this kind in config files and command options.
+    fn key(self) -> &'static str {
+        match self {
+            EventKind::Match => "match",
+            EventKind::Presence => "presence",
+            EventKind::Lifecycle => "lifecycle",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.key() == key)
+    }
+
+    fn prefix(self) -> &'static str {
+        match self {
+            EventKind::Match => MATCH_PREFIX.get().unwrap(),
+            EventKind::Presence => PRESENCE_PREFIX.get().unwrap(),
+            EventKind::Lifecycle => "",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            EventKind::Match => "dota",
+            EventKind::Presence => "activity",
+            EventKind::Lifecycle => "lifecycle",
+        }
+    }
+
+    /// The route for party games of this kind: `party_match` from routes.json
+    /// for matches when set, the kind's own route otherwise.
+    fn party_route(self) -> AnnouncementRoute {
+        let table = ROUTES.get().unwrap();
+        match table.party_match.clone().filter(|_| self == EventKind::Match) {
+            Some(mut route) => {
+                route.channels.retain(|channel| table.subscribed(*channel, self));
+                route
+            }
+            None => self.route(),
+        }
+    }
+
+    fn route(self) -> AnnouncementRoute {
+        let table = ROUTES.get().unwrap();
+        let mut route = table.routes.get(&self).cloned().unwrap_or_else(|| AnnouncementRoute {
+            // Nobody needs the bot's own status read aloud.
+            tts: self != EventKind::Lifecycle,
+            ..AnnouncementRoute::default()
+        });
+        route.channels.retain(|channel| table.subscribed(*channel, self));
+        route
+    }
+}
+
+/// Contents of `routes.json`: a route per event kind, plus optional
+/// per-channel subscriptions under the `subscriptions` key.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+struct RoutingTable {
+    /// Kinds each channel receives; channels not listed receive every kind.
+    #[serde(default)]
+    pub subscriptions: HashMap<ChannelId, Vec<EventKind>>,
+    /// Used instead of the `match` route for games played in a party.
+    #[serde(default)]
+    pub party_match: Option<AnnouncementRoute>,
+    #[serde(flatten)]
+    pub routes: HashMap<EventKind, AnnouncementRoute>,
+}
+
+impl RoutingTable {
+    fn subscribed(&self, channel: ChannelId, kind: EventKind) -> bool {
+        self.subscriptions
+            .get(&channel)
+            .is_none_or(|kinds| kinds.contains(&kind))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct AnnouncementRoute {
+    /// Every channel the announcement is mirrored to, possibly across guilds.
+    #[serde(default = "default_channels", alias = "channel", deserialize_with = "one_or_many")]
+    pub channels: Vec<ChannelId>,
+    #[serde(default = "default_tts")]
+    pub tts: bool,
+    #[serde(default)]
+    pub mention: Option<RoleId>,
+    #[serde(default)]
+    pub embed: bool,
+    /// Announcements of this kind arriving sooner after the previous one are dropped.
+    #[serde(default)]
+    pub min_interval_secs: u64,
+    /// Added to the bot's own announcements of this kind, e.g. for voting on
+    /// highlights. Takes the same forms as `REACTION`.
+    #[serde(default, deserialize_with = "parse_reaction")]
+    pub reaction: Option<ReactionType>,
+}
+
+impl Default for AnnouncementRoute {
+    fn default() -> Self {
+        AnnouncementRoute {
+            channels: default_channels(),
+            tts: default_tts(),
+            mention: None,
+            embed: false,
+            min_interval_secs: 0,
+            reaction: None,
+        }
+    }
+}
+
+fn default_channels() -> Vec<ChannelId> {
+    OUTPUT_CHANNEL
+        .get()
+        .unwrap()
+        .iter()
+        .map(|id| ChannelId::new(*id))
+        .collect()
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<ChannelId>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(ChannelId),
+        Many(Vec<ChannelId>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(channel) => vec![channel],
+        OneOrMany::Many(channels) => channels,
+    })
+}
+
+fn parse_reaction<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<ReactionType>, D::Error> {
+    let Some(reaction) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    reaction.trim().parse().map(Some).map_err(serde::de::Error::custom)
+}
+
+/// A localization string given either as one phrasing or as an array of
+/// alternatives, one of which is picked at random each time.
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+enum Phrase {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Phrase {
+    fn pick(&self) -> &str {
+        match self {
+            Phrase::One(phrase) => phrase,
+            Phrase::Many(phrases) if phrases.is_empty() => "",
+            Phrase::Many(phrases) => {
+                // Each RandomState gets fresh keys, which is random enough for flavor text.
+                let roll = RandomState::new().hash_one(());
+                &phrases[(roll % phrases.len() as u64) as usize]
+            }
+        }
+    }
+}
+
+fn default_tts() -> bool {
+    true
+}
+
+/// Source of the most recent announcement of a kind, kept so it can be
+/// re-rendered with the current localization and replayed.
+#[derive(Debug, Clone)]
+enum LastAnnouncement {
+    Match(Box<MatchData>),
+    Text(String),
+}
+
+/// A sent announcement as kept in the history and exported by `/export`.
+#[derive(Debug, Clone, Serialize)]
+struct AnnouncementRecord {
+    pub kind: &'static str,
+    pub text: String,
+    /// Unix time the announcement went out.
+    pub sent_at: u64,
+}
+
+/// Health bookkeeping for a polling loop.
+#[derive(Debug, Clone)]
+struct PollStats {
+    pub last_success: Option<SystemTime>,
+    pub consecutive_errors: u32,
+    pub total_errors: u64,
+}
+
+impl PollStats {
+    const fn new() -> Self {
+        PollStats {
+            last_success: None,
+            consecutive_errors: 0,
+            total_errors: 0,
+        }
+    }
+
+    fn record_success(&mut self) {
+        self.last_success = Some(SystemTime::now());
+        self.consecutive_errors = 0;
+    }
+
+    fn record_failure(&mut self) {
+        self.consecutive_errors += 1;
+        self.total_errors += 1;
+    }
+}
+
+/// What the bot currently believes the target is doing, as of the last presence update.
+#[derive(Debug, Clone)]
+struct PlayerState {
+    pub status: OnlineStatus,
+    pub game: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
 struct Localization {
     pub bot_activity: String,
     pub plays: String,
 
-    pub won: String,
-    pub lost: String,
+    /// Like the stomp, heartbreaker, break and high kill flavors, these may
+    /// list several phrasings to pick from at random.
+    pub won: Phrase,
+    pub lost: Phrase,
     pub played_on: String,
     pub with_score: String,
     pub match_duration: String,
     pub minutes: String,
+    /// Singular form of `minutes`; falls back to `minutes` when missing.
+    #[serde(default)]
+    pub minute: Option<String>,
 
     pub target_name: String,
     pub offline: String,
@@ -84,6 +509,187 @@ struct Localization {
     pub using_phone: String,
     pub using_browser: String,
     pub using_computer: String,
+
+    /// Put between the target's name and their new Steam persona name.
+    #[serde(default)]
+    pub steam_name_changed: Option<String>,
+    /// Put after the target's name, followed by the new Steam avatar.
+    #[serde(default)]
+    pub steam_avatar_changed: Option<String>,
+    /// Game added to the target's Steam library, with `{target_name}` and
+    /// `{game}` placeholders.
+    #[serde(default)]
+    pub game_added: Option<String>,
+
+    /// Status-only announcements keyed by transition such as `offline->online`,
+    /// with `{target_name}`, `{status}` and `{device}` placeholders.
+    #[serde(default)]
+    pub status_transitions: HashMap<String, String>,
+
+    /// Match announcement templates keyed by mode name (`turbo`, `ranked`),
+    /// with `{target_name}`, `{result}`, `{hero}`, `{kills}`, `{deaths}`,
+    /// `{assists}`, `{kda}` (in `KDA_STYLE`), `{minutes}`, `{minutes_str}`, `{gpm}`
+    /// and `{net_worth}` placeholders. Net worth needs a full match fetch and is empty otherwise.
+    #[serde(default)]
+    pub match_templates: HashMap<String, String>,
+
+    /// Long session nudge with `{target_name}`, `{game}` and `{hours}` placeholders.
+    #[serde(default)]
+    pub still_playing: Option<String>,
+
+    /// Note for a long Dota session without a finished match, with
+    /// `{target_name}` and `{minutes}` placeholders.
+    #[serde(default)]
+    pub no_match_yet: Option<String>,
+
+    /// Dota rich presence transitions, with `{target_name}` and `{side}` placeholders.
+    #[serde(default)]
+    pub dota_menu: Option<String>,
+    #[serde(default)]
+    pub dota_searching: Option<String>,
+    #[serde(default)]
+    pub dota_in_match: Option<String>,
+    /// Leaving the queue for a match, with `queue_announcements`.
+    #[serde(default)]
+    pub dota_match_found: Option<String>,
+    #[serde(default)]
+    pub radiant: Option<String>,
+    #[serde(default)]
+    pub dire: Option<String>,
+
+    /// Posted with the `announce_lifecycle` feature; English text when unset.
+    #[serde(default)]
+    pub watcher_started: Option<String>,
+    #[serde(default)]
+    pub watcher_stopping: Option<String>,
+
+    /// Hint posted once when the target's match history is private.
+    #[serde(default)]
+    pub history_hidden: Option<String>,
+
+    /// Party line appended to match announcements, with a `{members}` placeholder.
+    #[serde(default)]
+    pub party: Option<String>,
+    /// Added to matches played alone or in a party, with a `{size}`
+    /// placeholder. Skipped when OpenDota doesn't report the party size.
+    #[serde(default)]
+    pub solo_queue: Option<String>,
+    #[serde(default)]
+    pub party_queue: Option<String>,
+    /// Team composition line, with `{allies}` and `{enemies}` placeholders.
+    #[serde(default)]
+    pub teams: Option<String>,
+    /// Names for party members keyed by Steam32 account id.
+    #[serde(default)]
+    pub nicknames: HashMap<String, String>,
+    /// Emoji put before `target_name` in announcements, keyed by medal
+    /// (`herald` through `immortal`).
+    #[serde(default)]
+    pub medal_emoji: HashMap<String, String>,
+
+    /// Personal bests, with `{target_name}` plus `{streak}` or
+    /// `{kills}`, `{deaths}` and `{assists}` placeholders.
+    #[serde(default)]
+    pub record_streak: Option<String>,
+    #[serde(default)]
+    pub record_kda: Option<String>,
+    /// Reaching one of `MATCH_MILESTONES`, with `{target_name}` and `{count}`.
+    #[serde(default)]
+    pub match_milestone: Option<String>,
+
+    /// Immortal leaderboard movements, with `{target_name}` and `{rank}`;
+    /// `leaderboard_moved` also gets `{previous}`.
+    #[serde(default)]
+    pub leaderboard_entered: Option<String>,
+    #[serde(default)]
+    pub leaderboard_moved: Option<String>,
+    #[serde(default)]
+    pub leaderboard_left: Option<String>,
+
+    /// Added when the target's team drew first blood within
+    /// `FIRST_BLOOD_MAX_SECS`, with a `{time}` placeholder such as `0:45`.
+    /// Needs a parsed full match; skipped otherwise.
+    #[serde(default)]
+    pub first_blood: Option<String>,
+
+    /// Flavor for a hero unplayed for at least `HERO_DUST_DAYS`, with `{hero}`
+    /// and `{days}` placeholders.
+    #[serde(default)]
+    pub dusting_off: Option<String>,
+
+    /// Flavor for the first match after a `FIRST_MATCH_GAP_HOURS` break.
+    #[serde(default)]
+    pub back_in_action: Option<Phrase>,
+
+    /// Extra line for matches with at least `HIGH_KILLS` kills, with a `{kills}` placeholder.
+    #[serde(default)]
+    pub high_kills: Option<Phrase>,
+
+    /// Custom status change, with `{target_name}` and `{text}` placeholders.
+    #[serde(default)]
+    pub custom_status: Option<String>,
+
+    /// Posted once when the target leaves the guild, with a `{target_name}` placeholder.
+    /// Setting this or `target_rejoined` makes the bot request the privileged
+    /// GUILD_MEMBERS intent, so "Server Members Intent" must be enabled for
+    /// the bot in the developer portal; restart after changing either.
+    #[serde(default)]
+    pub target_left: Option<String>,
+    /// Posted when the target rejoins after leaving, with a `{target_name}` placeholder.
+    #[serde(default)]
+    pub target_rejoined: Option<String>,
+
+    /// Live match score, with `{target_name}`, `{radiant_score}`, `{dire_score}`
+    /// and `{minutes}` placeholders.
+    #[serde(default)]
+    pub live_score: Option<String>,
+
+    /// Newly unlocked Steam achievement, with `{target_name}`, `{achievement}`
+    /// and `{game}` placeholders.
+    #[serde(default)]
+    pub achievement_unlocked: Option<String>,
+
+    /// `STEAM_GROUP_ID` members starting a game, with `{name}` and `{game}`
+    /// placeholders, and coming online, with `{name}`. Coming online is only
+    /// announced when its template is set.
+    #[serde(default)]
+    pub group_member_playing: Option<String>,
+    #[serde(default)]
+    pub group_member_online: Option<String>,
+
+    /// Appended when the target stops playing, with `{game}`, `{minutes}`
+    /// and `{matches}` placeholders.
+    #[serde(default)]
+    pub session_stats: Option<String>,
+
+    /// Wrap-up posted when the target goes offline, with `{target_name}`,
+    /// `{count}`, `{wins}`, `{losses}` and `{best_hero}` placeholders.
+    #[serde(default)]
+    pub logoff_summary: Option<String>,
+    #[serde(default)]
+    pub logoff_summary_title: Option<String>,
+
+    /// Heads a batch of `ANNOUNCE_EVERY_N_MATCHES` matches, with `{target_name}`,
+    /// `{count}`, `{wins}` and `{losses}` placeholders. One line per match follows.
+    #[serde(default)]
+    pub batch_summary: Option<String>,
+
+    /// Stream announcement, edited in place when the title changes. Takes
+    /// `{target_name}`, `{title}`, `{url}` and `{viewers}` placeholders.
+    #[serde(default)]
+    pub stream_started: Option<String>,
+    /// Follow-up once streaming stops, with `{target_name}` and `{title}` placeholders.
+    #[serde(default)]
+    pub stream_ended: Option<String>,
+
+    /// Added to matches played on an alt account, with a `{label}` placeholder.
+    #[serde(default)]
+    pub alt_account: Option<String>,
+
+    #[serde(default)]
+    pub stomp: Option<Phrase>,
+    #[serde(default)]
+    pub heartbreaker: Option<Phrase>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -92,258 +698,3290 @@ struct Response<T> {
     pub items: Vec<T>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Deserialize)]
 struct Hero {
     pub id: i64,
     pub localized_name: String,
+    #[serde(default)]
+    pub primary_attr: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlayerData {
+    #[serde(default)]
+    pub profile: Option<PlayerProfile>,
+    /// Medal and stars as two digits, e.g. 54 for Legend 4.
+    #[serde(default)]
+    pub rank_tier: Option<i64>,
+    /// Only set for Immortal players on the leaderboard.
+    #[serde(default)]
+    pub leaderboard_rank: Option<i64>,
+}
+
+impl PlayerData {
+    fn history_hidden(&self) -> bool {
+        self.profile.as_ref().is_some_and(|profile| profile.fh_unavailable)
+    }
 }
 
 #[derive(Debug, Deserialize)]
+struct PlayerProfile {
+    /// Set when the player's full match history can't be fetched, i.e. it's private.
+    #[serde(default)]
+    pub fh_unavailable: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
 struct MatchData {
     pub match_id: i64,
     pub player_slot: i64,
-    pub radiant_win: bool,
     pub hero_id: i64,
-    pub duration: i64,
-    pub kills: i64,
-    pub deaths: i64,
-    pub assists: i64,
+    /// Missing while OpenDota hasn't parsed the match result yet.
+    #[serde(default)]
+    pub radiant_win: Option<bool>,
+    #[serde(default)]
+    pub duration: Option<i64>,
+    #[serde(default)]
+    pub kills: Option<i64>,
+    #[serde(default)]
+    pub deaths: Option<i64>,
+    #[serde(default)]
+    pub assists: Option<i64>,
+    #[serde(default)]
+    pub game_mode: i64,
+    #[serde(default)]
+    pub lobby_type: i64,
+    #[serde(default)]
+    pub party_size: Option<i64>,
+    /// Unix time the match started.
+    #[serde(default)]
+    pub start_time: Option<i64>,
+    #[serde(default)]
+    pub gold_per_min: Option<i64>,
+    /// Final net worth, filled in from the full match.
+    #[serde(skip)]
+    pub net_worth: Option<i64>,
+    /// Whether the match started `FIRST_MATCH_GAP_HOURS` after the previous one.
+    #[serde(skip)]
+    pub after_break: bool,
+    /// Days since the hero was last played, when at least `HERO_DUST_DAYS`.
+    #[serde(skip)]
+    pub hero_gap_days: Option<i64>,
+    /// Names of the target's party members, filled in from the full match.
+    #[serde(skip)]
+    pub party: Vec<String>,
+    /// Allied and enemy heroes, filled in from the full match.
+    #[serde(skip)]
+    pub teams: Option<(Vec<String>, Vec<String>)>,
+    /// When the target's team drew first blood, if within `FIRST_BLOOD_MAX_SECS`.
+    #[serde(skip)]
+    pub first_blood: Option<i64>,
+    /// `TEAMMATE_USERS` who played on the target's team, filled in from the full match.
+    #[serde(skip)]
+    pub teammates: Vec<UserId>,
+    /// Set when the match was played on one of `ALT_ACCOUNTS`.
+    #[serde(skip)]
+    pub alt: Option<AltAccount>,
+    /// Records and leaderboard moves that came with the match, posted right
+    /// after it so they share its filtering, delay and batching.
+    #[serde(skip)]
+    pub follow_ups: Vec<String>,
 }
 
-async fn set_heroes() -> Result<()> {
-    let body = reqwest::get("https://api.opendota.com/api/heroes")
-        .await?
-        .text()
-        .await?;
-    let mut heroes_hm: HashMap<i64, String> = HashMap::new();
-    let heroes: Response<Hero> = serde_json::from_str(&body)?;
-    for hero in heroes.items {
-        heroes_hm.insert(hero.id, hero.localized_name);
-    }
-    if HEROES.set(heroes_hm).is_err() {
-        return Err(anyhow!("Couldn't set HEROES"))
-    } 
-    Ok(())
+/// The parts of OpenDota's full match needed to find the target's party.
+#[derive(Debug, Deserialize)]
+struct FullMatch {
+    pub players: Vec<FullMatchPlayer>,
+    /// Only present once OpenDota has parsed the replay.
+    #[serde(default)]
+    pub objectives: Vec<Objective>,
 }
 
-async fn request_matches(url: &str) -> Result<Vec<MatchData>> {
-    let body = reqwest::get(url).await?.text().await?;
-    let response: Response<MatchData> = serde_json::from_str(&body)?;
-    Ok(response.items)
+#[derive(Debug, Deserialize)]
+struct Objective {
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Seconds since the horn; negative during the pre-game.
+    pub time: i64,
+    #[serde(default)]
+    pub player_slot: Option<i64>,
 }
 
-async fn main_loop(ctx: &Context) {
-    println!("Dotawatcher enabled");
-    let mut interval = time::interval(MAIN_LOOP_INTERVAL);
-    let mut last_match_id = 0;
-    let locals = &LOCALIZATION.get().unwrap();
-    let matches_url = format!(
-        "https://api.opendota.com/api/players/{}/recentMatches",
-        &TARGET_STEAMID32.get().unwrap()
-    );
-    loop {
-        interval.tick().await;
+#[derive(Debug, Deserialize)]
+struct FullMatchPlayer {
+    /// Missing for players hiding their account.
+    #[serde(default)]
+    pub account_id: Option<u64>,
+    #[serde(default)]
+    pub party_id: Option<i64>,
+    #[serde(default)]
+    pub personaname: Option<String>,
+    #[serde(default)]
+    pub player_slot: i64,
+    #[serde(default)]
+    pub hero_id: i64,
+    #[serde(default)]
+    pub net_worth: Option<i64>,
+}
 
-        if HEROES.get().is_none() {
-            if let Err(err) = set_heroes().await {
-                eprintln!("Error fetching heroes: {err}");
-                continue;
+/// Radiant slots are 0-4 and Dire slots 128-132.
+fn is_radiant_slot(player_slot: i64) -> bool {
+    player_slot < 128
+}
+
+impl FullMatch {
+    /// When first blood fell, if the team playing `player_slot` drew it.
+    fn team_first_blood(&self, player_slot: i64) -> Option<i64> {
+        let first_blood = self
+            .objectives
+            .iter()
+            .find(|objective| objective.kind == "CHAT_MESSAGE_FIRSTBLOOD")?;
+        let killer = first_blood.player_slot?;
+        (is_radiant_slot(killer) == is_radiant_slot(player_slot)).then_some(first_blood.time)
+    }
+
+    /// Hero names of the target's teammates and of their opponents.
+    fn teams(&self, player_slot: i64) -> (Vec<String>, Vec<String>) {
+        let heroes = HEROES.get().unwrap();
+        let mut allies = Vec::new();
+        let mut enemies = Vec::new();
+        for player in self.players.iter().filter(|player| player.player_slot != player_slot) {
+            let hero = heroes
+                .get(&player.hero_id)
+                .map_or_else(|| player.hero_id.to_string(), |hero| hero.localized_name.clone());
+            if is_radiant_slot(player.player_slot) == is_radiant_slot(player_slot) {
+                allies.push(hero);
+            } else {
+                enemies.push(hero);
             }
         }
+        (allies, enemies)
+    }
 
-        let matches = match request_matches(&matches_url).await {
-            Ok(matches) => matches,
-            Err(err) => {
-                eprintln!("Couldn't fetch matches: {err}");
-                continue;
-            }
-        };
-        let last = match matches.first() {
-            Some(last) => last,
-            None => {
-                eprintln!("Empty matches list");
-                continue;
-            }
+    /// Discord users mapped in `users`, such as `TEAMMATE_USERS`, on the same
+    /// team as `player_slot`.
+    fn mapped_teammates(&self, player_slot: i64, users: &HashMap<u64, UserId>) -> Vec<UserId> {
+        self.players
+            .iter()
+            .filter(|player| {
+                player.player_slot != player_slot
+                    && is_radiant_slot(player.player_slot) == is_radiant_slot(player_slot)
+            })
+            .filter_map(|player| users.get(&player.account_id?).copied())
+            .collect()
+    }
+
+    /// Names of everyone in `account_id`'s party except them, preferring
+    /// configured nicknames, then Steam names, then the raw account id.
+    fn party_members(&self, account_id: u64) -> Vec<String> {
+        let Some(party_id) = self
+            .players
+            .iter()
+            .find(|player| player.account_id == Some(account_id))
+            .and_then(|player| player.party_id)
+        else {
+            return Vec::new();
         };
-        if last.match_id == last_match_id {
-            continue;
-        }
+        let nicknames = &LOCALIZATION.get().unwrap().nicknames;
+        self.players
+            .iter()
+            .filter(|player| {
+                player.party_id == Some(party_id) && player.account_id != Some(account_id)
+            })
+            .filter_map(|player| {
+                let id = player.account_id?.to_string();
+                Some(
+                    nicknames
+                        .get(&id)
+                        .or(player.personaname.as_ref())
+                        .cloned()
+                        .unwrap_or(id),
+                )
+            })
+            .collect()
+    }
+}
 
-        if last_match_id == 0 {
-            last_match_id = last.match_id;
-            continue;
+impl MatchData {
+    /// Whether the match was ranked matchmaking, as kept by `ranked_only`.
+    fn is_ranked(&self) -> bool {
+        self.lobby_type == LOBBY_TYPE_RANKED
+    }
+
+    fn is_win(&self) -> bool {
+        self.radiant_win == Some(self.player_slot < 5)
+    }
+
+    fn kda(&self) -> KdaRecord {
+        KdaRecord {
+            match_id: self.match_id,
+            kills: self.kills.unwrap_or_default(),
+            deaths: self.deaths.unwrap_or_default(),
+            assists: self.assists.unwrap_or_default(),
         }
-        last_match_id = last.match_id;
+    }
 
-        let result = if last.radiant_win == (last.player_slot < 5) {
-            &locals.won
-        } else {
-            &locals.lost
-        };
+    /// Whether the target queued with others; `None` when the party size is unknown.
+    fn in_party(&self) -> Option<bool> {
+        self.party_size.map(|size| size > 1)
+    }
 
-        let mut message: CreateMessage = Default::default();
-        message = message.content(format!(
-"{target_name} {result}. {played_on} {hero} {with_score} {kills}, {deaths}, {assists}. {match_duration} {minutes} {minutes_str}.",
-            target_name = locals.target_name,
-            result = result,
-            hero = HEROES.get().unwrap().get(&last.hero_id).unwrap(),
-            kills = last.kills,
-            deaths = last.deaths,
-            assists = last.assists,
-            minutes = last.duration / 60,
-            played_on = locals.played_on,
-            with_score = locals.with_score,
-            match_duration = locals.match_duration,
-            minutes_str = locals.minutes,
-        ));
-        message = message.tts(true);
+    fn minutes(&self) -> i64 {
+        self.duration.unwrap_or_default() / 60
+    }
 
-        if let Err(why) = ChannelId::new(*OUTPUT_CHANNEL.get().unwrap())
-            .send_message(ctx.http(), message)
-            .await
-        {
-            eprintln!("Error sending dota message: {why:?}");
+    /// Names of the optional stats OpenDota left out, shown as zero.
+    fn missing_stats(&self) -> Vec<&'static str> {
+        [
+            ("duration", self.duration),
+            ("kills", self.kills),
+            ("deaths", self.deaths),
+            ("assists", self.assists),
+        ]
+        .into_iter()
+        .filter(|(_, value)| value.is_none())
+        .map(|(name, _)| name)
+        .collect()
+    }
+
+    /// Key into `Localization::match_templates` for modes with their own phrasing.
+    fn mode_name(&self) -> Option<&'static str> {
+        match (self.game_mode, self.lobby_type) {
+            (GAME_MODE_TURBO, _) => Some("turbo"),
+            (_, LOBBY_TYPE_RANKED) => Some("ranked"),
+            _ => None,
         }
     }
 }
 
-struct Handler {
-    dotawatcher_active: Mutex<bool>,
+/// What the color of a match embed conveys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EmbedColorScheme {
+    Outcome,
+    Attribute,
 }
 
-#[async_trait]
-impl EventHandler for Handler {
-    async fn message(&self, ctx: Context, msg: Message) {
-        if msg.author.id == UserId::new(*TARGET_USER.get().unwrap()) {
-            let reaction = ReactionType::Custom {
-                animated: false,
-                id: EmojiId::new(*EMOJI_ID.get().unwrap()),
-                name: Some(EMOJI_NAME.get().unwrap().clone()),
-            };
-            if let Err(why) = msg.react(&ctx.http, reaction).await {
-                eprintln!("Error reacting to message: {why:?}");
-            }
+impl FromStr for EmbedColorScheme {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "outcome" => Ok(EmbedColorScheme::Outcome),
+            "attribute" => Ok(EmbedColorScheme::Attribute),
+            _ => Err(anyhow!("expected outcome or attribute, got {s}")),
         }
     }
+}
 
-    async fn presence_update(&self, ctx: Context, new_data: Presence) {
-        if new_data.guild_id != Some(GuildId::new(*TARGET_GUILD.get().unwrap()))
-            || new_data.user.id != *TARGET_USER.get().unwrap()
-        {
-            return;
-        }
+/// Another account of the target's, named in announcements of its matches.
+#[derive(Debug, Clone)]
+struct AltAccount {
+    pub id: u64,
+    pub label: String,
+}
 
-        let username = &LOCALIZATION.get().unwrap().target_name;
+/// `ALT_ACCOUNTS` as `steam32:label` pairs separated by commas.
+#[derive(Debug, Clone, Default)]
+struct AltAccounts(pub Vec<AltAccount>);
 
-        let mut message: CreateMessage = Default::default();
-        message = message.tts(true);
-        let mut status: &str = get_string_for_status!(new_data.status);
+impl FromStr for AltAccounts {
+    type Err = anyhow::Error;
 
-        let device = new_data.client_status.map_or("", |device| {
-            if let Some(s) = device.mobile {
-                status = get_string_for_status!(s);
-                &LOCALIZATION.get().unwrap().using_phone
-            } else if let Some(s) = device.web {
-                status = get_string_for_status!(s);
-                &LOCALIZATION.get().unwrap().using_browser
-            } else {
-                status = get_string_for_status!(new_data.status);
-                &LOCALIZATION.get().unwrap().using_computer
-            }
-        });
+    fn from_str(s: &str) -> Result<Self> {
+        s.split(',')
+            .filter(|item| !item.trim().is_empty())
+            .map(|item| {
+                let (id, label) = item
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("expected steam32:label, got {item}"))?;
+                Ok(AltAccount {
+                    id: id.trim().parse()?,
+                    label: label.trim().to_string(),
+                })
+            })
+            .collect::<Result<_>>()
+            .map(AltAccounts)
+    }
+}
 
-        if new_data.activities.is_empty() {
-            message = message.content(format!("{} {}{}", username, status, device));
-        } else {
-            let activity = &new_data.activities[0];
+/// `TEAMMATE_USERS` as `steam32:discord user id` pairs separated by commas.
+#[derive(Debug, Clone, Default)]
+struct TeammateUsers(pub HashMap<u64, UserId>);
 
-            let activity_name: &str;
-            let activity_details: &Option<String>;
-            if activity.kind == ActivityType::Custom {
-                activity_name = activity.details.as_deref().unwrap_or_default();
-                activity_details = &None;
-            } else {
-                activity_name = &activity.name;
-                activity_details = &activity.details;
-            }
+impl FromStr for TeammateUsers {
+    type Err = anyhow::Error;
 
-            let large_text: &Option<String>;
-            let small_text: &Option<String>;
-            if let Some(assets) = activity.assets.as_ref() {
-                large_text = &assets.large_text;
-                small_text = &assets.small_text;
-            } else {
-                large_text = &None;
-                small_text = &None;
+    fn from_str(s: &str) -> Result<Self> {
+        let mut users = HashMap::new();
+        for item in s.split(',').filter(|item| !item.trim().is_empty()) {
+            let (steam_id, user_id) = item
+                .split_once(':')
+                .ok_or_else(|| anyhow!("expected steam32:user id, got {item}"))?;
+            // A zero id would make `UserId::new` panic, skip the entry instead.
+            match user_id.trim().parse::<NonZeroU64>() {
+                Ok(user_id) => {
+                    users.insert(steam_id.trim().parse()?, UserId::from(user_id));
+                }
+                Err(err) => eprintln!("Ignoring TEAMMATE_USERS entry {item}: {err}"),
             }
+        }
+        Ok(TeammateUsers(users))
+    }
+}
 
-            message = message.content(format!(
-                "{} {}{} {} {}\n{}\n{}\n{}",
-                username,
-                status,
-                device,
-                &LOCALIZATION.get().unwrap().plays,
-                activity_name,
-                activity_details.as_deref().unwrap_or_default(),
-                large_text.as_deref().unwrap_or_default(),
-                small_text.as_deref().unwrap_or_default(),
-            ));
-        }
-        if let Err(why) = ChannelId::new(*OUTPUT_CHANNEL.get().unwrap())
-            .send_message(ctx.http(), message)
-            .await
-        {
-            eprintln!("Error sending activity message: {why:?}");
+/// Which match outcomes get announced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResultFilter {
+    Wins,
+    Losses,
+    Both,
+}
+
+impl ResultFilter {
+    fn allows(self, win: bool) -> bool {
+        match self {
+            ResultFilter::Wins => win,
+            ResultFilter::Losses => !win,
+            ResultFilter::Both => true,
         }
     }
+}
 
-    async fn ready(&self, ctx: Context, ready: Ready) {
-        println!("{} is connected!", ready.user.name);
-        let mut activity = ActivityData::custom("");
-        activity.state = Some(LOCALIZATION.get().unwrap().bot_activity.clone());
-        ctx.set_activity(Some(activity));
+impl FromStr for ResultFilter {
+    type Err = anyhow::Error;
 
-        let mut dota_active = self.dotawatcher_active.lock().await;
-        if !*dota_active {
-            *dota_active = true;
-            tokio::spawn(async move {
-                main_loop(&ctx).await;
-            });
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "wins" => Ok(ResultFilter::Wins),
+            "losses" => Ok(ResultFilter::Losses),
+            "both" => Ok(ResultFilter::Both),
+            _ => Err(anyhow!("expected wins, losses or both, got {s}")),
         }
     }
 }
 
-#[tokio::main]
-async fn main() {
-    dotenv::dotenv().ok();
+/// How kills, deaths and assists are written, picked with `KDA_STYLE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KdaStyle {
+    /// `12, 3, 18`
+    Comma,
+    /// `12/3/18`
+    Slash,
+    /// `⚔️12/💀3/🤝18`
+    Emoji,
+}
 
-    let token = env::var("DISCORD_TOKEN").expect("Expected a token in the environment");
+impl FromStr for KdaStyle {
+    type Err = anyhow::Error;
 
-    set_env_num!(TARGET_GUILD);
-    set_env_num!(OUTPUT_CHANNEL);
-    set_env_num!(TARGET_USER);
-    set_env_num!(TARGET_STEAMID32);
-    set_env_num!(EMOJI_ID);
-    set_env_str!(EMOJI_NAME);
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "comma" => Ok(KdaStyle::Comma),
+            "slash" => Ok(KdaStyle::Slash),
+            "emoji" => Ok(KdaStyle::Emoji),
+            _ => Err(anyhow!("expected comma, slash or emoji, got {s}")),
+        }
+    }
+}
 
-    let locals: Localization = serde_json::from_str(
-        &std::fs::read_to_string("localization.json")
-            .expect("localization.json file in the root folder"),
-    )
-    .unwrap_or_else(|err| panic!("Invalid localization.json: {err}"));
-    LOCALIZATION.set(locals).unwrap();
+fn format_kda(m: &MatchData, style: KdaStyle) -> String {
+    let kills = m.kills.unwrap_or_default();
+    let deaths = m.deaths.unwrap_or_default();
+    let assists = m.assists.unwrap_or_default();
+    match style {
+        KdaStyle::Comma => format!("{kills}, {deaths}, {assists}"),
+        KdaStyle::Slash => format!("{kills}/{deaths}/{assists}"),
+        KdaStyle::Emoji => format!("\u{2694}\u{fe0f}{kills}/\u{1f480}{deaths}/\u{1f91d}{assists}"),
+    }
+}
 
-    let intents = GatewayIntents::GUILD_MESSAGES
-        | GatewayIntents::DIRECT_MESSAGES
-        | GatewayIntents::MESSAGE_CONTENT
-        | GatewayIntents::GUILD_PRESENCES;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MatchFlavor {
+    Stomp,
+    Heartbreaker,
+    Regular,
+}
 
-    let mut client = Client::builder(&token, intents)
-        .event_handler(Handler { dotawatcher_active: Mutex::new(false), })
-        .await
-        .expect("Successfull client creation");
+/// Short wins are stomps, long losses are heartbreakers.
+fn classify_match(m: &MatchData) -> MatchFlavor {
+    let minutes = m.minutes();
+    let kills = m.kills.unwrap_or_default();
+    let deaths = m.deaths.unwrap_or_default();
+    if m.is_win() && minutes <= *STOMP_MAX_MINUTES.get().unwrap() && kills >= deaths {
+        MatchFlavor::Stomp
+    } else if !m.is_win() && minutes >= *HEARTBREAKER_MIN_MINUTES.get().unwrap() {
+        MatchFlavor::Heartbreaker
+    } else {
+        MatchFlavor::Regular
+    }
+}
 
-    if let Err(why) = client.start().await {
-        eprintln!("Client error: {why:?}");
+/// Builds the URL of an OpenDota endpoint such as `heroes` under `OPENDOTA_BASE_URL`.
+fn opendota_url(path: &str) -> String {
+    format!("{}/{}", OPENDOTA_BASE_URL.get().unwrap(), path.trim_start_matches('/'))
+}
+
+/// Fetches an OpenDota endpoint, waiting for the shared rate budget first.
+async fn opendota_get(url: &str) -> Result<String, FetchError> {
+    OPENDOTA_LIMITER.get().unwrap().acquire().await;
+    let response = http_client().get(url).send().await?.error_for_status()?;
+    read_capped(url, response).await
+}
+
+/// Reads the body of a response from `url`, capped at `MAX_BODY_BYTES` so a
+/// misbehaving endpoint can't make the bot buffer an arbitrarily large body.
+async fn read_capped(url: &str, mut response: reqwest::Response) -> Result<String, FetchError> {
+    let too_large =
+        || FetchError::Api(format!("Response from {url} exceeds {MAX_BODY_BYTES} bytes"));
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_BODY_BYTES as u64)
+    {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if body.len() + chunk.len() > MAX_BODY_BYTES {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(String::from_utf8(body)?)
+}
+
+/// One HTTP client for every outbound request, so connections are reused.
+fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(reqwest::Client::new)
+}
+
+/// Body POSTed to `EXTERNAL_WEBHOOK_URL` for every new match.
+#[derive(Debug, Serialize)]
+struct WebhookPayload {
+    match_id: i64,
+    /// Steam32 id of the account the match was played on, main or alt.
+    player: u64,
+    hero: String,
+    win: bool,
+    kills: i64,
+    deaths: i64,
+    assists: i64,
+}
+
+async fn post_match_webhook(last: MatchData) {
+    send_match_webhook(EXTERNAL_WEBHOOK_URL.get().unwrap(), &last).await;
+}
+
+/// `gap_days` since the hero was last played, if at least `dust_days`.
+/// A `dust_days` of 0 turns the flavor off.
+fn dusted_off(gap_days: Option<i64>, dust_days: i64) -> Option<i64> {
+    gap_days.filter(|days| dust_days > 0 && *days >= dust_days)
+}
+
+/// Whether a polled match gets announced. A fresh deployment seeds its state
+/// silently unless asked to welcome with the last match.
+fn announces_new_match(first_run: bool, already_seen: bool, seed_announce: bool) -> bool {
+    !already_seen && (!first_run || seed_announce)
+}
+
+/// Whether a match starting at `start` came more than `gap_hours` after the
+/// previous one started. A `gap_hours` of 0 turns the check off.
+fn after_break(previous: Option<i64>, start: Option<i64>, gap_hours: i64) -> bool {
+    match (previous, start) {
+        (Some(previous), Some(start)) => gap_hours > 0 && start - previous > gap_hours * 3600,
+        _ => false,
+    }
+}
+
+/// POSTs `last` to `url`, retrying a few times before giving up.
+async fn send_match_webhook(url: &str, last: &MatchData) {
+    let hero = HEROES
+        .get()
+        .unwrap()
+        .get(&last.hero_id)
+        .map_or_else(|| last.hero_id.to_string(), |hero| hero.localized_name.clone());
+    let kda = last.kda();
+    let payload = WebhookPayload {
+        match_id: last.match_id,
+        player: last.alt.as_ref().map_or(*TARGET_STEAMID32.get().unwrap(), |alt| alt.id),
+        hero,
+        win: last.is_win(),
+        kills: kda.kills,
+        deaths: kda.deaths,
+        assists: kda.assists,
+    };
+    for attempt in 1..=WEBHOOK_ATTEMPTS {
+        let sent = http_client().post(url).json(&payload).send().await;
+        let err = match sent.and_then(|response| response.error_for_status()) {
+            Ok(_) => return,
+            Err(err) => err,
+        };
+        eprintln!("Webhook attempt {attempt} for match {} failed: {err}", last.match_id);
+        if attempt < WEBHOOK_ATTEMPTS {
+            time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+        }
+    }
+}
+
+/// Fetches the hero list, replacing any loaded before. Returns how many heroes
+/// were loaded.
+async fn set_heroes() -> Result<usize, FetchError> {
+    let heroes = parse_heroes(&opendota_get(&opendota_url("heroes")).await?)?;
+    let count = heroes.len();
+    HEROES.replace(heroes);
+    Ok(count)
+}
+
+/// OpenDota's hero list keyed by hero id.
+fn parse_heroes(body: &str) -> Result<HashMap<i64, Hero>, FetchError> {
+    let heroes: Response<Hero> = serde_json::from_str(body)?;
+    Ok(heroes.items.into_iter().map(|hero| (hero.id, hero)).collect())
+}
+
+async fn request_player() -> Result<PlayerData, FetchError> {
+    let url = opendota_url(&format!("players/{}", TARGET_STEAMID32.get().unwrap()));
+    Ok(serde_json::from_str(&opendota_get(&url).await?)?)
+}
+
+async fn history_hidden() -> Result<bool, FetchError> {
+    Ok(request_player().await?.history_hidden())
+}
+
+/// Refreshes the medal and leaderboard position, if anything uses them.
+/// Returns how the persisted leaderboard rank changed.
+async fn refresh_player_rank() -> Option<LeaderboardChange> {
+    let locals = LOCALIZATION.get().unwrap();
+    let wants_leaderboard = locals.leaderboard_entered.is_some()
+        || locals.leaderboard_moved.is_some()
+        || locals.leaderboard_left.is_some();
+    if locals.medal_emoji.is_empty() && !wants_leaderboard {
+        return None;
+    }
+    let player = match request_player().await {
+        Ok(player) => player,
+        Err(err) => {
+            eprintln!("Couldn't fetch rank tier: {err}");
+            return None;
+        }
+    };
+    *RANK_TIER.lock().unwrap() = player.rank_tier;
+    if !wants_leaderboard {
+        return None;
+    }
+    update_state(|state| state.record_leaderboard_rank(player.leaderboard_rank))
+}
+
+/// Medal name for an OpenDota `rank_tier`, e.g. 54 (Legend 4) is `legend`.
+fn rank_medal(rank_tier: i64) -> Option<&'static str> {
+    let bracket = usize::try_from(rank_tier / 10).ok()?.checked_sub(1)?;
+    RANK_MEDALS.get(bracket).copied()
+}
+
+/// `target_name` prefixed with the emoji configured for the target's medal.
+fn target_name() -> String {
+    let locals = LOCALIZATION.get().unwrap();
+    let rank_tier = *RANK_TIER.lock().unwrap();
+    with_medal_emoji(&locals.target_name, rank_tier, &locals.medal_emoji)
+}
+
+/// `name` prefixed with the emoji `medal_emoji` has for `rank_tier`'s medal, if any.
+fn with_medal_emoji(
+    name: &str,
+    rank_tier: Option<i64>,
+    medal_emoji: &HashMap<String, String>,
+) -> String {
+    match rank_tier.and_then(rank_medal).and_then(|medal| medal_emoji.get(medal)) {
+        Some(emoji) => format!("{emoji} {name}"),
+        None => name.to_string(),
+    }
+}
+
+/// Posts a one-time hint when OpenDota reports the target's match history as private.
+async fn note_hidden_history() {
+    static NOTED: StdMutex<bool> = StdMutex::new(false);
+    if *NOTED.lock().unwrap() {
+        return;
+    }
+    match history_hidden().await {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(err) => {
+            eprintln!("Couldn't check match history privacy: {err}");
+            return;
+        }
+    }
+    *NOTED.lock().unwrap() = true;
+    let text = LOCALIZATION.get().unwrap().history_hidden.as_deref().unwrap_or(
+        "Match history looks private. Enable \"Expose Public Match Data\" in Dota settings.",
+    );
+    announce(Announcement::new(EventKind::Lifecycle, text.to_string())).await;
+}
+
+async fn request_full_match(match_id: i64) -> Result<FullMatch, FetchError> {
+    let body = opendota_get(&opendota_url(&format!("matches/{match_id}"))).await?;
+    Ok(serde_json::from_str(&body)?)
+}
+
+/// Fills in the parts of `last` that need the full match, if any feature asks for them.
+async fn add_full_match_details(last: &mut MatchData) {
+    let features = FEATURES.get().unwrap();
+    let wants_party = features.party_members && last.party_size.unwrap_or(1) > 1;
+    let wants_first_blood = LOCALIZATION.get().unwrap().first_blood.is_some();
+    let wants_teammates = !TEAMMATE_USERS.get().unwrap().0.is_empty();
+    if !wants_party && !features.team_heroes && !wants_first_blood && !wants_teammates {
+        return;
+    }
+    let full_match = match request_full_match(last.match_id).await {
+        Ok(full_match) => full_match,
+        Err(err) => {
+            eprintln!("Couldn't fetch full match {}: {err}", last.match_id);
+            return;
+        }
+    };
+    if wants_party {
+        let account_id = last.alt.as_ref().map_or(*TARGET_STEAMID32.get().unwrap(), |alt| alt.id);
+        last.party = full_match.party_members(account_id);
+    }
+    if features.team_heroes {
+        last.teams = Some(full_match.teams(last.player_slot));
+    }
+    if wants_teammates {
+        let users = &TEAMMATE_USERS.get().unwrap().0;
+        last.teammates = full_match.mapped_teammates(last.player_slot, users);
+    }
+    if wants_first_blood {
+        last.first_blood = full_match
+            .team_first_blood(last.player_slot)
+            .filter(|time| *time <= *FIRST_BLOOD_MAX_SECS.get().unwrap());
+    }
+    last.net_worth = full_match
+        .players
+        .iter()
+        .find(|player| player.player_slot == last.player_slot)
+        .and_then(|player| player.net_worth);
+}
+
+/// Formats in-game seconds as `m:ss`, e.g. `0:45` or `-0:30` before the horn.
+fn format_game_time(secs: i64) -> String {
+    let sign = if secs < 0 { "-" } else { "" };
+    format!("{sign}{}:{:02}", secs.abs() / 60, secs.abs() % 60)
+}
+
+/// Formats gold amounts, as `18.4k` with the `abbreviate_numbers` feature.
+fn humanize_number(n: i64) -> String {
+    format_number(n, FEATURES.get().unwrap().abbreviate_numbers)
+}
+
+/// `n` as is, or abbreviated to one decimal of thousands or millions.
+fn format_number(n: i64, abbreviate: bool) -> String {
+    if !abbreviate || n.abs() < 1000 {
+        return n.to_string();
+    }
+    // Round before picking the unit, so 999_950 becomes 1M rather than 1000k.
+    let mut tenths = (n as f64 / 100.0).round();
+    let mut suffix = "k";
+    if tenths.abs() >= 10_000.0 {
+        tenths = (n as f64 / 100_000.0).round();
+        suffix = "M";
+    }
+    let formatted = format!("{:.1}", tenths / 10.0);
+    format!("{}{suffix}", formatted.trim_end_matches(".0"))
+}
+
+/// Picks the singular or plural form for `n` following English rules.
+fn pluralize<'a>(n: i64, one: &'a str, many: &'a str) -> &'a str {
+    if n == 1 {
+        one
+    } else {
+        many
+    }
+}
+
+/// Shortens `s` to at most `max` characters, cutting on a grapheme boundary
+/// and marking the cut with an ellipsis.
+fn clamp_message(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        return s.to_string();
+    }
+    let mut clamped = String::new();
+    let mut len = 0;
+    for grapheme in s.graphemes(true) {
+        let grapheme_len = grapheme.chars().count();
+        if len + grapheme_len + 1 > max {
+            break;
+        }
+        len += grapheme_len;
+        clamped.push_str(grapheme);
+    }
+    if max > 0 {
+        clamped.push('…');
+    }
+    clamped
+}
+
+/// Time left until the presence channel counts as quiet again, if people
+/// are currently chatting in it.
+fn channel_active_remaining() -> Option<Duration> {
+    let window = Duration::from_secs(*ACTIVE_CHANNEL_WINDOW_SECS.get().unwrap());
+    let last = (*LAST_HUMAN_MESSAGE.lock().unwrap())?;
+    active_remaining(last, window, Instant::now())
+}
+
+/// Time left at `now` of the `window` that started with the last human message.
+fn active_remaining(last: Instant, window: Duration, now: Instant) -> Option<Duration> {
+    window.checked_sub(now.duration_since(last)).filter(|left| !left.is_zero())
+}
+
+/// Posts an automatic announcement unless the bot runs in passive mode, where
+/// announcements are only remembered for commands such as `/replay`.
+/// Returns whether the announcement went out.
+async fn announce(announcement: Announcement) -> bool {
+    if announcements_paused() || throttled(announcement.kind) {
+        return false;
+    }
+    notify(announcement).await;
+    true
+}
+
+/// Like `announce`, for posts that belong to one already announced, such as
+/// the records a match set or a stream's new title. They aren't held to the
+/// kind's minimum interval, which the announcement they follow has started.
+async fn announce_follow_up(announcement: Announcement) {
+    if announcements_paused() {
+        return;
+    }
+    notify(announcement).await;
+}
+
+/// Whether passive mode or `/mute` currently hold back automatic posts.
+fn announcements_paused() -> bool {
+    let muted_until = *MUTED_UNTIL.lock().unwrap();
+    paused(FEATURES.get().unwrap().passive_mode, muted_until, Instant::now())
+}
+
+/// Whether automatic posts are held back at `now`, in passive mode or while
+/// muted until `muted_until`.
+fn paused(passive_mode: bool, muted_until: Option<Instant>, now: Instant) -> bool {
+    passive_mode || muted_until.is_some_and(|until| now < until)
+}
+
+/// Whether `kind` was announced less than its route's `min_interval_secs` ago.
+/// Otherwise records now as its last announcement time.
+fn throttled(kind: EventKind) -> bool {
+    let min_interval = Duration::from_secs(kind.route().min_interval_secs);
+    if inside_min_interval(&mut LAST_SENT.lock().unwrap(), kind, min_interval) {
+        eprintln!("Dropping {} announcement inside its minimum interval", kind.name());
+        return true;
+    }
+    false
+}
+
+/// Whether `last_sent` has `kind` going out less than `min_interval` ago.
+/// Otherwise records now as its last time.
+fn inside_min_interval(
+    last_sent: &mut BTreeMap<EventKind, Instant>,
+    kind: EventKind,
+    min_interval: Duration,
+) -> bool {
+    if last_sent.get(&kind).is_some_and(|sent| sent.elapsed() < min_interval) {
+        return true;
+    }
+    last_sent.insert(kind, Instant::now());
+    false
+}
+
+/// Hands `announcement` to every configured notifier.
+async fn notify(announcement: Announcement) {
+    for notifier in NOTIFIERS.get().unwrap() {
+        if let Err(err) = notifier.send(&announcement).await {
+            eprintln!("Error sending {} announcement: {err}", announcement.kind.name());
+        }
+    }
+    let mut history = ANNOUNCEMENT_HISTORY.lock().unwrap();
+    if history.len() >= *HISTORY_CAP.get().unwrap() {
+        history.pop_front();
+    }
+    history.push_back(AnnouncementRecord {
+        kind: announcement.kind.key(),
+        text: announcement.text,
+        sent_at: SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    });
+}
+
+/// Substitutes `{name}` placeholders in a localization template.
+fn render_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut rendered = template.to_string();
+    for (name, value) in values {
+        rendered = rendered.replace(&format!("{{{name}}}"), value);
+    }
+    rendered
+}
+
+/// Picks the won or lost phrasing for `last`. Callers pick once per match so
+/// every part of its announcement agrees when there are several phrasings.
+fn match_result(last: &MatchData) -> &'static str {
+    let locals = LOCALIZATION.get().unwrap();
+    if last.is_win() {
+        locals.won.pick()
+    } else {
+        locals.lost.pick()
+    }
+}
+
+fn format_match(last: &MatchData, result: &str) -> String {
+    let locals = &LOCALIZATION.get().unwrap();
+
+    let minutes = last.minutes();
+    let kills = last.kills.unwrap_or_default();
+    let deaths = last.deaths.unwrap_or_default();
+    let assists = last.assists.unwrap_or_default();
+    let kda = format_kda(last, *KDA_STYLE.get().unwrap());
+    let hero = &HEROES
+        .get()
+        .unwrap()
+        .get(&last.hero_id)
+        .map_or_else(|| last.hero_id.to_string(), |hero| hero.localized_name.clone());
+    let minutes_str = pluralize(
+        minutes,
+        locals.minute.as_deref().unwrap_or(&locals.minutes),
+        &locals.minutes,
+    );
+    let template = last
+        .mode_name()
+        .and_then(|mode| locals.match_templates.get(mode));
+    let mut content = match template {
+        Some(template) => render_template(
+            template,
+            &[
+                ("target_name", &target_name()),
+                ("result", result),
+                ("hero", hero),
+                ("kills", &kills.to_string()),
+                ("deaths", &deaths.to_string()),
+                ("assists", &assists.to_string()),
+                ("kda", &kda),
+                ("minutes", &minutes.to_string()),
+                ("minutes_str", minutes_str),
+                ("gpm", &last.gold_per_min.map_or(String::new(), |gpm| gpm.to_string())),
+                ("net_worth", &last.net_worth.map_or(String::new(), humanize_number)),
+            ],
+        ),
+        None => format!(
+"{target_name} {result}. {played_on} {hero} {with_score} {kda}. {match_duration} {minutes} {minutes_str}.",
+            target_name = target_name(),
+            result = result,
+            hero = hero,
+            kda = kda,
+            minutes = minutes,
+            played_on = locals.played_on,
+            with_score = locals.with_score,
+            match_duration = locals.match_duration,
+            minutes_str = minutes_str,
+        ),
+    };
+
+    if let Some(alt) = &last.alt {
+        let template = locals.alt_account.as_deref().unwrap_or("(on their alt {label})");
+        content.push(' ');
+        content.push_str(&render_template(template, &[("label", &alt.label)]));
+    }
+
+    let queue = match last.in_party() {
+        Some(true) => locals.party_queue.as_deref(),
+        Some(false) => locals.solo_queue.as_deref(),
+        None => None,
+    };
+    if let (Some(template), Some(size)) = (queue, last.party_size) {
+        content.push(' ');
+        content.push_str(&render_template(template, &[("size", &size.to_string())]));
+    }
+
+    let flavor = match classify_match(last) {
+        MatchFlavor::Stomp => locals.stomp.as_ref(),
+        MatchFlavor::Heartbreaker => locals.heartbreaker.as_ref(),
+        MatchFlavor::Regular => None,
+    };
+    if let Some(flavor) = flavor {
+        content.push(' ');
+        content.push_str(flavor.pick());
+    }
+    if let (Some(template), Some(time)) = (locals.first_blood.as_deref(), last.first_blood) {
+        content.push(' ');
+        content.push_str(&render_template(template, &[("time", &format_game_time(time))]));
+    }
+    if let (Some(template), Some(days)) = (locals.dusting_off.as_deref(), last.hero_gap_days) {
+        content.push(' ');
+        content.push_str(&render_template(
+            template,
+            &[("hero", hero), ("days", &days.to_string())],
+        ));
+    }
+    if let Some(back) = locals.back_in_action.as_ref().filter(|_| last.after_break) {
+        content.push(' ');
+        content.push_str(back.pick());
+    }
+    if let Some(carried) = locals.high_kills.as_ref().map(Phrase::pick) {
+        if kills >= *HIGH_KILLS.get().unwrap() {
+            content.push(' ');
+            content.push_str(&render_template(carried, &[("kills", &kills.to_string())]));
+        }
+    }
+    if !last.party.is_empty() {
+        let template = locals.party.as_deref().unwrap_or("Party: {members}.");
+        content.push('\n');
+        content.push_str(&render_template(template, &[("members", &last.party.join(", "))]));
+    }
+    if let Some((allies, enemies)) = &last.teams {
+        let template = locals.teams.as_deref().unwrap_or("With {allies} vs {enemies}.");
+        let line = render_template(
+            template,
+            &[("allies", &allies.join(", ")), ("enemies", &enemies.join(", "))],
+        );
+        content.push('\n');
+        content.push_str(&clamp_message(&line, TEAMS_LINE_LIMIT));
+    }
+    content
+}
+
+fn format_record(record: NewRecord) -> Option<String> {
+    let locals = LOCALIZATION.get().unwrap();
+    let name = target_name();
+    let target_name = ("target_name", name.as_str());
+    match record {
+        NewRecord::Streak(streak) => Some(render_template(
+            locals.record_streak.as_deref()?,
+            &[target_name, ("streak", &streak.to_string())],
+        )),
+        NewRecord::Kda(kda) => Some(render_template(
+            locals.record_kda.as_deref()?,
+            &[
+                target_name,
+                ("kills", &kda.kills.to_string()),
+                ("deaths", &kda.deaths.to_string()),
+                ("assists", &kda.assists.to_string()),
+            ],
+        )),
+        NewRecord::Milestone(count) => Some(render_template(
+            locals
+                .match_milestone
+                .as_deref()
+                .unwrap_or("Match number {count} for {target_name}!"),
+            &[target_name, ("count", &count.to_string())],
+        )),
+    }
+}
+
+fn format_leaderboard_change(change: LeaderboardChange) -> Option<String> {
+    let locals = LOCALIZATION.get().unwrap();
+    let name = target_name();
+    let (template, rank, previous) = match change {
+        LeaderboardChange::Entered(rank) => (locals.leaderboard_entered.as_deref()?, rank, None),
+        LeaderboardChange::Moved { from, to } => {
+            (locals.leaderboard_moved.as_deref()?, to, Some(from))
+        }
+        LeaderboardChange::Left(rank) => (locals.leaderboard_left.as_deref()?, rank, None),
+    };
+    Some(render_template(
+        template,
+        &[
+            ("target_name", &name),
+            ("rank", &rank.to_string()),
+            ("previous", &previous.map_or(String::new(), |previous| previous.to_string())),
+        ],
+    ))
+}
+
+/// Applies `update` to the shared state and persists the result.
+fn update_state<T>(update: impl FnOnce(&mut PersistentState) -> T) -> T {
+    let mut state = STATE.get().unwrap().lock().unwrap();
+    let result = update(&mut state);
+    let path = STATE_FILE.get().unwrap();
+    if let Err(err) = state.save(path) {
+        eprintln!("Couldn't save state to {}: {err}", path.display());
+    }
+    result
+}
+
+/// Adds `match_id` to the persisted seen set, returning false if it was already there.
+fn mark_match_seen(match_id: i64) -> bool {
+    let mut seen = SEEN_MATCHES.get().unwrap().lock().unwrap();
+    if !seen.insert(match_id, *MATCH_LRU_CAP.get().unwrap()) {
+        return false;
+    }
+    let path = SEEN_MATCHES_FILE.get().unwrap();
+    if let Err(err) = seen.save(path) {
+        eprintln!("Couldn't save seen matches to {}: {err}", path.display());
+    }
+    true
+}
+
+fn remember_announcement(kind: EventKind, announcement: LastAnnouncement) {
+    LAST_ANNOUNCEMENTS.lock().unwrap().insert(kind, announcement);
+}
+
+fn record_game_seen(game: &str) {
+    GAME_LAST_SEEN.lock().unwrap().insert(game.to_string(), Instant::now());
+}
+
+/// Whether `game` was announced or left less than `GAME_REANNOUNCE_SECS` ago.
+fn game_seen_recently(game: &str) -> bool {
+    let window = Duration::from_secs(*GAME_REANNOUNCE_SECS.get().unwrap());
+    seen_within(&mut GAME_LAST_SEEN.lock().unwrap(), game, Instant::now(), window)
+}
+
+/// Whether `game` was seen less than `window` before `now`. Such a relaunch
+/// counts as seeing the game again, so one restarted over and over stays
+/// quiet until it's been gone for a whole window.
+fn seen_within(
+    last_seen: &mut BTreeMap<String, Instant>,
+    game: &str,
+    now: Instant,
+    window: Duration,
+) -> bool {
+    let recent = last_seen.get(game).is_some_and(|seen| now.duration_since(*seen) < window);
+    if recent {
+        last_seen.insert(game.to_string(), now);
+    }
+    recent
+}
+
+/// Posts the last announcement of `kind` again. Returns false if there is none.
+async fn replay_announcement(kind: EventKind) -> bool {
+    let last = LAST_ANNOUNCEMENTS.lock().unwrap().get(&kind).cloned();
+    match last {
+        Some(LastAnnouncement::Match(last)) => notify(match_announcement(&last)).await,
+        Some(LastAnnouncement::Text(content)) => notify(Announcement::new(kind, content)).await,
+        None => return false,
+    }
+    true
+}
+
+fn match_announcement(last: &MatchData) -> Announcement {
+    let hero = &HEROES
+        .get()
+        .unwrap()
+        .get(&last.hero_id)
+        .map_or_else(|| last.hero_id.to_string(), |hero| hero.localized_name.clone());
+    let result = match_result(last);
+    Announcement::new(EventKind::Match, format_match(last, result))
+        .with_embed(build_match_embed(last))
+        .with_thread_title(format!("{hero}: {result}"))
+        .with_mentions(last.teammates.clone())
+        .with_party(last.in_party() == Some(true))
+}
+
+fn build_match_embed(last: &MatchData) -> EmbedData {
+    let hero = HEROES.get().unwrap().get(&last.hero_id);
+    let name = hero.map_or_else(|| last.hero_id.to_string(), |hero| hero.localized_name.clone());
+    match MATCH_EMBED_COLORS.get().unwrap() {
+        EmbedColorScheme::Outcome => {
+            let color = if last.is_win() { Colour::DARK_GREEN } else { Colour::RED };
+            EmbedData {
+                title: Some(name),
+                color: Some(color.0),
+            }
+        }
+        EmbedColorScheme::Attribute => {
+            let outcome = if last.is_win() { "🏆" } else { "💀" };
+            EmbedData {
+                title: Some(format!("{outcome} {name}")),
+                color: Some(attribute_color(hero.map_or("", |hero| &hero.primary_attr)).0),
+            }
+        }
+    }
+}
+
+fn attribute_color(primary_attr: &str) -> Colour {
+    match primary_attr {
+        "str" => Colour::RED,
+        "agi" => Colour::DARK_GREEN,
+        "int" => Colour::BLUE,
+        "all" => Colour::PURPLE,
+        _ => Colour::default(),
+    }
+}
+
+/// Waits out failures that polling again right away won't fix.
+async fn back_off(err: Option<&FetchError>) {
+    if let Some(FetchError::RateLimited) = err {
+        eprintln!("Rate limited, pausing polling for {}s", RATE_LIMIT_BACKOFF.as_secs());
+        time::sleep(RATE_LIMIT_BACKOFF).await;
+    }
+}
+
+/// The newest match across the target's accounts, tagged with the alt it
+/// was played on. Fails if any account can't be fetched, so an outage on the
+/// newest account doesn't announce an older match from another one.
+async fn latest_match(
+    sources: &[(Option<AltAccount>, Box<dyn DotaSource>)],
+) -> Result<Option<MatchData>> {
+    let mut latest: Option<MatchData> = None;
+    for (alt, source) in sources {
+        let Some(mut candidate) = source.recent_matches().await?.into_iter().next() else {
+            continue;
+        };
+        let newer = latest.as_ref().is_none_or(|latest| {
+            (candidate.start_time, candidate.match_id) > (latest.start_time, latest.match_id)
+        });
+        if newer {
+            candidate.alt = alt.clone();
+            latest = Some(candidate);
+        }
+    }
+    Ok(latest)
+}
+
+async fn main_loop() {
+    println!("Dotawatcher enabled");
+    let mut interval = time::interval(MAIN_LOOP_INTERVAL);
+    let source = *MATCH_SOURCE.get().unwrap();
+    if source == MatchSource::Valve && STEAM_API_KEY.get().unwrap().is_empty() {
+        eprintln!("MATCH_SOURCE=valve needs STEAM_API_KEY, match polling disabled");
+        return;
+    }
+    let source_for = |account_id| -> Box<dyn DotaSource> {
+        match source {
+            MatchSource::OpenDota => Box::new(OpenDotaSource::new(account_id)),
+            MatchSource::Valve => Box::new(ValveSource::new(account_id)),
+        }
+    };
+    let mut sources = vec![(None, source_for(*TARGET_STEAMID32.get().unwrap()))];
+    for alt in &ALT_ACCOUNTS.get().unwrap().0 {
+        sources.push((Some(alt.clone()), source_for(alt.id)));
+    }
+    // Empty results repeat every poll until the target plays, so warn once per streak.
+    let mut warned_empty = false;
+    let mut rank_refreshed: Option<Instant> = None;
+    let mut waiting_for_result = None;
+    loop {
+        interval.tick().await;
+
+        if HEROES.get().is_none() {
+            if let Err(err) = set_heroes().await {
+                eprintln!("Error fetching heroes: {err}");
+                DOTA_POLL_STATS.lock().unwrap().record_failure();
+                back_off(Some(&err)).await;
+                continue;
+            }
+        }
+
+        // The medal is needed before the next match, e.g. right after a restart.
+        if rank_refreshed.is_none_or(|refreshed| refreshed.elapsed() >= RANK_REFRESH_INTERVAL) {
+            rank_refreshed = Some(Instant::now());
+            let change = refresh_player_rank().await;
+            // A fresh state file seeds the leaderboard rank silently, like the last match.
+            let seeding = STATE.get().unwrap().lock().unwrap().last_match_id == 0;
+            if let Some(content) = change.filter(|_| !seeding).and_then(format_leaderboard_change) {
+                announce(Announcement::new(EventKind::Match, content)).await;
+            }
+        }
+
+        let latest = match latest_match(&sources).await {
+            Ok(latest) => latest,
+            Err(err) => {
+                eprintln!("Couldn't fetch matches: {err}");
+                DOTA_POLL_STATS.lock().unwrap().record_failure();
+                back_off(err.downcast_ref()).await;
+                continue;
+            }
+        };
+        DOTA_POLL_STATS.lock().unwrap().record_success();
+        let last = match &latest {
+            Some(last) => {
+                warned_empty = false;
+                last
+            }
+            None => {
+                if !warned_empty {
+                    warned_empty = true;
+                    eprintln!("Empty matches list");
+                    note_hidden_history().await;
+                }
+                continue;
+            }
+        };
+        let last_match_id = STATE.get().unwrap().lock().unwrap().last_match_id;
+        if last.match_id == last_match_id {
+            continue;
+        }
+
+        if last.radiant_win.is_none() {
+            if result_wait_over(&mut waiting_for_result, last.match_id) {
+                eprintln!("Match {} still has no result, skipping it", last.match_id);
+                update_state(|state| state.last_match_id = last.match_id);
+            } else {
+                eprintln!("Match {} has no result yet, retrying later", last.match_id);
+            }
+            continue;
+        }
+        let missing = last.missing_stats();
+        if !missing.is_empty() {
+            eprintln!("Match {} is missing {}", last.match_id, missing.join(", "));
+        }
+
+        let first_run = last_match_id == 0;
+        let already_seen = !mark_match_seen(last.match_id);
+        let mut last = last.clone();
+        let records = update_state(|state| {
+            state.last_match_id = last.match_id;
+            if already_seen {
+                return Vec::new();
+            }
+            let gap_hours = *FIRST_MATCH_GAP_HOURS.get().unwrap();
+            last.after_break = after_break(state.last_match_start, last.start_time, gap_hours);
+            state.last_match_start = last.start_time.or(state.last_match_start);
+            if let Some(start) = last.start_time {
+                let gap_days = state.record_hero(last.hero_id, start);
+                last.hero_gap_days = dusted_off(gap_days, *HERO_DUST_DAYS.get().unwrap());
+            }
+            [
+                state.record_match_count(MATCH_MILESTONES.get().unwrap()),
+                state.record_result(last.is_win()),
+                state.record_kda(last.kda()),
+            ]
+            .into_iter()
+            .flatten()
+            .collect()
+        });
+        session::record_match_finished();
+        let leaderboard_change = refresh_player_rank().await;
+        rank_refreshed = Some(Instant::now());
+        let seed_announce = FEATURES.get().unwrap().seed_announce_last_match;
+        if !announces_new_match(first_run, already_seen, seed_announce) {
+            continue;
+        }
+        if FEATURES.get().unwrap().ranked_only && !last.is_ranked() {
+            continue;
+        }
+        {
+            let mut session_matches = ONLINE_SESSION_MATCHES.lock().unwrap();
+            // Someone who never goes offline would otherwise grow this forever.
+            if session_matches.len() >= *HISTORY_CAP.get().unwrap() {
+                session_matches.remove(0);
+            }
+            session_matches.push(last.clone());
+        }
+        if !EXTERNAL_WEBHOOK_URL.get().unwrap().is_empty() {
+            tokio::spawn(post_match_webhook(last.clone()));
+        }
+        last.follow_ups = records
+            .into_iter()
+            .filter_map(format_record)
+            .chain(leaderboard_change.and_then(format_leaderboard_change))
+            .collect();
+        if !ANNOUNCE_RESULTS.get().unwrap().allows(last.is_win()) {
+            continue;
+        }
+        if *ANNOUNCE_EVERY_N_MATCHES.get().unwrap() > 1 {
+            let batch = {
+                let mut pending = PENDING_BATCH.lock().unwrap();
+                pending.push(last);
+                if pending.len() < *ANNOUNCE_EVERY_N_MATCHES.get().unwrap() {
+                    continue;
+                }
+                std::mem::take(&mut *pending)
+            };
+            announce_batch(&batch).await;
+            continue;
+        }
+
+        let delay = Duration::from_secs(*MATCH_ANNOUNCE_DELAY_SECS.get().unwrap());
+        schedule_match_announcement(last, delay).await;
+    }
+}
+
+/// Announces `last` right away, or after `delay` from a task of its own to give
+/// OpenDota time to parse the match without holding up polling.
+async fn schedule_match_announcement(last: MatchData, delay: Duration) -> Option<JoinHandle<()>> {
+    if delay.is_zero() {
+        announce_match(last).await;
+        return None;
+    }
+    Some(tokio::spawn(async move {
+        time::sleep(delay).await;
+        announce_match(last).await;
+    }))
+}
+
+/// Counts another poll of `match_id` without a result, returning whether it
+/// has now waited `RESULT_WAIT_POLLS` times.
+fn result_wait_over(waiting: &mut Option<(i64, u32)>, match_id: i64) -> bool {
+    let polls = match waiting {
+        Some((waiting_for, polls)) if *waiting_for == match_id => polls,
+        _ => &mut waiting.insert((match_id, 0)).1,
+    };
+    *polls += 1;
+    *polls >= RESULT_WAIT_POLLS
+}
+
+async fn announce_match(mut last: MatchData) {
+    add_full_match_details(&mut last).await;
+    announce(match_announcement(&last)).await;
+    for content in std::mem::take(&mut last.follow_ups) {
+        announce_follow_up(Announcement::new(EventKind::Match, content)).await;
+    }
+    remember_announcement(EventKind::Match, LastAnnouncement::Match(Box::new(last)));
+}
+
+/// The announcement for `activity`, after `who`: the target's name, status
+/// and device. Compact messages leave out the asset texts and fit one line.
+fn activity_message(who: &str, activity: &Activity, compact: bool) -> String {
+    let activity_name: &str;
+    let activity_details: &Option<String>;
+    if activity.kind == ActivityType::Custom {
+        activity_name = activity.details.as_deref().unwrap_or_default();
+        activity_details = &None;
+    } else {
+        activity_name = &activity.name;
+        activity_details = &activity.details;
+    }
+
+    let large_text: &Option<String>;
+    let small_text: &Option<String>;
+    if let Some(assets) = activity.assets.as_ref() {
+        large_text = &assets.large_text;
+        small_text = &assets.small_text;
+    } else {
+        large_text = &None;
+        small_text = &None;
+    }
+    let activity_name = sanitize_activity_text(activity_name);
+    let activity_details = sanitize_activity_text(activity_details.as_deref().unwrap_or_default());
+    let large_text = sanitize_activity_text(large_text.as_deref().unwrap_or_default());
+    let small_text = sanitize_activity_text(small_text.as_deref().unwrap_or_default());
+
+    let plays = &LOCALIZATION.get().unwrap().plays;
+    if compact {
+        let mut content = format!("{who} {plays} {activity_name}");
+        if !activity_details.is_empty() {
+            content.push_str(", ");
+            content.push_str(&activity_details);
+        }
+        content
+    } else {
+        format!("{who} {plays} {activity_name}\n{activity_details}\n{large_text}\n{small_text}")
+    }
+}
+
+/// Flattens user-controlled activity text onto one line: control characters
+/// become spaces and whitespace runs collapse to a single space.
+fn sanitize_activity_text(s: &str) -> String {
+    s.split(|c: char| c.is_control() || c.is_whitespace())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The `status_transitions` template for going from `from` to `to`, keyed like `offline->online`.
+fn transition_template(
+    transitions: &HashMap<String, String>,
+    from: OnlineStatus,
+    to: OnlineStatus,
+) -> Option<&String> {
+    transitions.get(&format!("{}->{}", status_key(from), status_key(to)))
+}
+
+/// Name of a status in `Localization::status_transitions` keys.
+fn status_key(status: OnlineStatus) -> &'static str {
+    match status {
+        OnlineStatus::Offline => "offline",
+        OnlineStatus::Idle => "idle",
+        OnlineStatus::Invisible => "invisible",
+        OnlineStatus::Online => "online",
+        OnlineStatus::DoNotDisturb => "donotdisturb",
+        _ => "unknown",
+    }
+}
+
+/// Posts the matches collected for `ANNOUNCE_EVERY_N_MATCHES` as one message.
+async fn announce_batch(matches: &[MatchData]) {
+    if matches.is_empty() {
+        return;
+    }
+    let locals = LOCALIZATION.get().unwrap();
+    let wins = matches.iter().filter(|m| m.is_win()).count();
+    let template = locals
+        .batch_summary
+        .as_deref()
+        .unwrap_or("{target_name}'s last {count} matches: {wins}W-{losses}L");
+    let mut content = render_template(
+        template,
+        &[
+            ("target_name", &target_name()),
+            ("count", &matches.len().to_string()),
+            ("wins", &wins.to_string()),
+            ("losses", &(matches.len() - wins).to_string()),
+        ],
+    );
+    for m in matches {
+        let hero = HEROES
+            .get()
+            .unwrap()
+            .get(&m.hero_id)
+            .map_or_else(|| m.hero_id.to_string(), |hero| hero.localized_name.clone());
+        let result = match_result(m);
+        let kda = format_kda(m, *KDA_STYLE.get().unwrap());
+        content.push_str(&format!("\n{hero}: {result}, {kda}"));
+    }
+    remember_announcement(EventKind::Match, LastAnnouncement::Text(content.clone()));
+    announce(Announcement::new(EventKind::Match, content)).await;
+    for content in matches.iter().flat_map(|m| m.follow_ups.iter()) {
+        announce_follow_up(Announcement::new(EventKind::Match, content.clone())).await;
+    }
+}
+
+/// Wrap-up of the matches played since the target last came online.
+fn logoff_summary(matches: &[MatchData]) -> Option<Announcement> {
+    let best = matches.iter().max_by(|a, b| a.kda().ratio().total_cmp(&b.kda().ratio()))?;
+    let wins = matches.iter().filter(|m| m.is_win()).count();
+    let locals = LOCALIZATION.get().unwrap();
+    let best_hero = HEROES
+        .get()
+        .unwrap()
+        .get(&best.hero_id)
+        .map_or_else(|| best.hero_id.to_string(), |hero| hero.localized_name.clone());
+    let template = locals.logoff_summary.as_deref().unwrap_or(
+        "{target_name} played {count} Dota matches: {wins}W-{losses}L, best hero {best_hero}.",
+    );
+    let text = render_template(
+        template,
+        &[
+            ("target_name", &target_name()),
+            ("count", &matches.len().to_string()),
+            ("wins", &wins.to_string()),
+            ("losses", &(matches.len() - wins).to_string()),
+            ("best_hero", &best_hero),
+        ],
+    );
+    let embed = EmbedData {
+        title: locals.logoff_summary_title.clone(),
+        color: None,
+    };
+    Some(Announcement::new(EventKind::Match, text).with_embed(embed))
+}
+
+/// Records whether the target is out of the guild, returning whether that
+/// changed, so repeated member events announce only once.
+fn set_target_left(target_left: &mut bool, left: bool) -> bool {
+    std::mem::replace(target_left, left) != left
+}
+
+/// Text of the custom status among `activities`, if the target has one set.
+fn custom_status_text(activities: &[Activity]) -> Option<String> {
+    activities
+        .iter()
+        .find(|activity| activity.kind == ActivityType::Custom)
+        .and_then(|activity| activity.state.clone().or(activity.details.clone()))
+}
+
+/// Stores `custom` as the `last` custom status, returning whether it changed.
+fn record_custom_status(last: &mut Option<String>, custom: &Option<String>) -> bool {
+    let changed = last != custom;
+    last.clone_from(custom);
+    changed
+}
+
+/// Picks the activity worth announcing: games and streams first, a custom
+/// status last. Ties keep Discord's order, whatever it happens to be.
+fn primary_activity<'a>(
+    activities: impl IntoIterator<Item = &'a Activity>,
+) -> Option<&'a Activity> {
+    activities.into_iter().min_by_key(|activity| match activity.kind {
+        ActivityType::Playing | ActivityType::Streaming => 0,
+        ActivityType::Custom => 2,
+        _ => 1,
+    })
+}
+
+/// Whether an activity passes the `ALLOWED_ACTIVITY_APP_IDS` filter.
+fn activity_allowed(activity: &Activity) -> bool {
+    let without_app_id = FEATURES.get().unwrap().allow_activities_without_app_id;
+    app_id_allowed(activity, ALLOWED_ACTIVITY_APP_IDS.get().unwrap(), without_app_id)
+}
+
+fn app_id_allowed(activity: &Activity, allowed: &[u64], without_app_id: bool) -> bool {
+    if allowed.is_empty() {
+        return true;
+    }
+    match activity.application_id {
+        Some(id) => allowed.contains(&id.get()),
+        None => without_app_id,
+    }
+}
+
+struct Handler {
+    /// Started by the first `ready`; later ones come from full reconnects and
+    /// must not reset the loops' in-memory state.
+    watchers: Mutex<Option<Watchers>>,
+    sink: Arc<dyn MessageSink>,
+}
+
+/// The loops spawned on the first `ready`. A loop that returned on its own
+/// is dropped, since a finished `JoinHandle` must not be awaited again.
+struct Watchers {
+    session: Option<JoinHandle<()>>,
+    dota: Option<JoinHandle<()>>,
+    steam_profile: Option<JoinHandle<()>>,
+    library: Option<JoinHandle<()>>,
+    achievements: Option<JoinHandle<()>>,
+    live_scores: Option<JoinHandle<()>>,
+    steam_group: Option<JoinHandle<()>>,
+}
+
+/// Restarts the task behind `handle` if it panicked. Tasks that returned on
+/// their own, e.g. because the config gives them nothing to do, stay stopped.
+async fn revive<F: Future<Output = ()> + Send + 'static>(
+    handle: &mut Option<JoinHandle<()>>,
+    name: &str,
+    task: impl FnOnce() -> F,
+) {
+    if handle.as_ref().is_none_or(|running| !running.is_finished()) {
+        return;
+    }
+    let finished = handle.take().unwrap();
+    if let Err(err) = finished.await {
+        if err.is_panic() {
+            eprintln!("{name} died, restarting it");
+            *handle = Some(tokio::spawn(task()));
+        }
+    }
+}
+
+/// Spawns the watcher tasks with `start` and announces the start on the first
+/// `ready`. Later ones come from new gateway sessions, where the tasks are
+/// still running and only those that died are restarted.
+async fn start_watchers(watchers: &mut Option<Watchers>, start: impl FnOnce() -> Watchers) {
+    match watchers.as_mut() {
+        Some(watchers) => {
+            println!("Reconnected with a new gateway session, keeping the watcher tasks");
+            revive(&mut watchers.session, "Session loop", session::session_loop).await;
+            revive(&mut watchers.dota, "Dota loop", main_loop).await;
+            revive(
+                &mut watchers.steam_profile,
+                "Steam profile loop",
+                steam_profile::steam_profile_loop,
+            )
+            .await;
+            revive(&mut watchers.library, "Library loop", library::library_loop).await;
+            revive(
+                &mut watchers.achievements,
+                "Achievements loop",
+                achievements::achievements_loop,
+            )
+            .await;
+            revive(&mut watchers.live_scores, "Live score loop", live::live_score_loop).await;
+            revive(&mut watchers.steam_group, "Steam group loop", steam_group::steam_group_loop)
+                .await;
+        }
+        None => {
+            if FEATURES.get().unwrap().announce_lifecycle {
+                let locals = LOCALIZATION.get().unwrap();
+                let text = locals.watcher_started.as_deref().unwrap_or("Watcher started");
+                announce(Announcement::new(EventKind::Lifecycle, text.to_string())).await;
+            }
+            *watchers = Some(start());
+        }
+    }
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn message(&self, _ctx: Context, msg: Message) {
+        let features = FEATURES.get().unwrap();
+        let presence_channel = EventKind::Presence.route().channels.contains(&msg.channel_id);
+        if !features.disable_presence && !msg.author.bot && presence_channel {
+            *LAST_HUMAN_MESSAGE.lock().unwrap() = Some(Instant::now());
+        }
+
+        let from_target = msg.author.id == UserId::new(*TARGET_USER.get().unwrap());
+        if !features.disable_reactions && from_target {
+            react_to_target(self.sink.as_ref(), msg.channel_id, msg.id).await;
+        }
+    }
+
+    async fn guild_member_removal(
+        &self,
+        _ctx: Context,
+        guild_id: GuildId,
+        user: User,
+        _member: Option<Member>,
+    ) {
+        if guild_id.get() != *TARGET_GUILD.get().unwrap() || user.id != *TARGET_USER.get().unwrap()
+        {
+            return;
+        }
+        if !set_target_left(&mut TARGET_LEFT_GUILD.lock().unwrap(), true) {
+            return;
+        }
+        eprintln!("Target left the guild, presence watching paused until they rejoin");
+        if let Some(template) = LOCALIZATION.get().unwrap().target_left.as_deref() {
+            let content = render_template(template, &[("target_name", &target_name())]);
+            announce(Announcement::new(EventKind::Presence, content)).await;
+        }
+    }
+
+    async fn guild_member_addition(&self, _ctx: Context, new_member: Member) {
+        if new_member.guild_id.get() != *TARGET_GUILD.get().unwrap()
+            || new_member.user.id != *TARGET_USER.get().unwrap()
+        {
+            return;
+        }
+        if !set_target_left(&mut TARGET_LEFT_GUILD.lock().unwrap(), false) {
+            return;
+        }
+        eprintln!("Target rejoined the guild, presence watching resumed");
+        if let Some(template) = LOCALIZATION.get().unwrap().target_rejoined.as_deref() {
+            let content = render_template(template, &[("target_name", &target_name())]);
+            announce(Announcement::new(EventKind::Presence, content)).await;
+        }
+    }
+
+    async fn presence_update(&self, _ctx: Context, new_data: Presence) {
+        if FEATURES.get().unwrap().disable_presence
+            || new_data.guild_id != Some(GuildId::new(*TARGET_GUILD.get().unwrap()))
+            || new_data.user.id != *TARGET_USER.get().unwrap()
+        {
+            return;
+        }
+
+        let username = &target_name();
+
+        let mut online_status = new_data.status;
+
+        let device = new_data.client_status.map_or("", |device| {
+            if let Some(s) = device.mobile {
+                online_status = s;
+                &LOCALIZATION.get().unwrap().using_phone
+            } else if let Some(s) = device.web {
+                online_status = s;
+                &LOCALIZATION.get().unwrap().using_browser
+            } else {
+                &LOCALIZATION.get().unwrap().using_computer
+            }
+        });
+        let status: &str = get_string_for_status!(online_status);
+
+        let game = primary_activity(&new_data.activities)
+            .filter(|activity| activity.kind != ActivityType::Custom)
+            .map(|activity| activity.name.clone());
+        let finished_session = session::track_game(game.as_deref());
+        let previous = CURRENT_STATE.lock().unwrap().replace(PlayerState {
+            status: online_status,
+            game: game.clone(),
+        });
+        let previous_game = previous.as_ref().and_then(|previous| previous.game.clone());
+        if let Some(left) = previous_game.as_deref().filter(|left| game.as_deref() != Some(*left)) {
+            record_game_seen(left);
+        }
+        let was_online = previous
+            .as_ref()
+            .is_some_and(|previous| previous.status != OnlineStatus::Offline);
+        if online_status == OnlineStatus::Offline && was_online {
+            let matches = std::mem::take(&mut *ONLINE_SESSION_MATCHES.lock().unwrap());
+            // Don't carry an unfinished batch over into the next session.
+            let batch = std::mem::take(&mut *PENDING_BATCH.lock().unwrap());
+            announce_batch(&batch).await;
+            if FEATURES.get().unwrap().logoff_summary {
+                if let Some(summary) = logoff_summary(&matches) {
+                    announce(summary).await;
+                }
+            }
+        } else if online_status != OnlineStatus::Offline && !was_online {
+            ONLINE_SESSION_MATCHES.lock().unwrap().clear();
+        }
+
+        let custom_statuses = FEATURES.get().unwrap().custom_status_announcements;
+        if custom_statuses {
+            let custom = custom_status_text(&new_data.activities);
+            let changed = record_custom_status(&mut LAST_CUSTOM_STATUS.lock().unwrap(), &custom);
+            if let Some(text) = custom.filter(|_| changed) {
+                let template = LOCALIZATION.get().unwrap().custom_status.as_deref();
+                let content = render_template(
+                    template.unwrap_or("{target_name} changed their status to: {text}"),
+                    &[("target_name", username), ("text", &sanitize_activity_text(&text))],
+                );
+                remember_announcement(EventKind::Presence, LastAnnouncement::Text(content.clone()));
+                announce(Announcement::new(EventKind::Presence, content)).await;
+            }
+            let nothing_else_changed = previous
+                .as_ref()
+                .is_some_and(|previous| previous.status == online_status && previous.game == game);
+            if changed && nothing_else_changed {
+                return;
+            }
+        }
+
+        // With custom status announcements on, custom statuses get their own line above.
+        let allowed = new_data.activities.iter().filter(|activity| {
+            let handled_above = custom_statuses && activity.kind == ActivityType::Custom;
+            activity_allowed(activity) && !handled_above
+        });
+        let activity = primary_activity(allowed);
+
+        if FEATURES.get().unwrap().stream_updates {
+            let streaming = new_data
+                .activities
+                .iter()
+                .find(|activity| stream::is_streaming(activity) && activity_allowed(activity));
+            stream::update(streaming).await;
+            if streaming.is_some() {
+                return;
+            }
+        }
+
+        if FEATURES.get().unwrap().dota_rich_presence {
+            if let Some(activity) = activity.filter(|activity| dota_presence::is_dota(activity)) {
+                let transition = dota_presence::transition(dota_presence::parse(activity));
+                let lines = transition.map_or(Vec::new(), |(previous, presence)| {
+                    dota_presence::announcements(previous, presence)
+                });
+                for content in lines {
+                    remember_announcement(
+                        EventKind::Presence,
+                        LastAnnouncement::Text(content.clone()),
+                    );
+                    announce(Announcement::new(EventKind::Presence, content)).await;
+                }
+                return;
+            }
+            dota_presence::transition(None);
+        }
+
+        let entered = game.as_deref().filter(|game| previous_game.as_deref() != Some(*game));
+        if let Some(entered) = entered {
+            if game_seen_recently(entered) {
+                eprintln!("Not announcing {entered} again, it was played moments ago");
+                return;
+            }
+        }
+
+        let content = if let Some(activity) = activity {
+            let who = format!("{username} {status}{device}");
+            activity_message(&who, activity, FEATURES.get().unwrap().compact_messages)
+        } else {
+            let transitions = &LOCALIZATION.get().unwrap().status_transitions;
+            let template = previous.and_then(|previous| {
+                transition_template(transitions, previous.status, online_status)
+            });
+            let mut content = match template {
+                Some(template) => render_template(
+                    template,
+                    &[("target_name", username), ("status", status), ("device", device)],
+                ),
+                None => format!("{} {}{}", username, status, device),
+            };
+            let stats_template = LOCALIZATION.get().unwrap().session_stats.as_deref();
+            if let (Some(template), Some(stats)) = (stats_template, finished_session) {
+                content.push('\n');
+                content.push_str(&stats.render(template));
+            }
+            content
+        };
+        if let Some(game) = &game {
+            record_game_seen(game);
+        }
+        remember_announcement(EventKind::Presence, LastAnnouncement::Text(content.clone()));
+        announce(Announcement::new(EventKind::Presence, content)).await;
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::Command(command) = interaction {
+            commands::handle(&ctx, &command).await;
+        }
+    }
+
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        println!("{} is connected!", ready.user.name);
+        commands::register(&ctx).await;
+        let mut activity = ActivityData::custom("");
+        activity.state = Some(LOCALIZATION.get().unwrap().bot_activity.clone());
+        ctx.set_activity(Some(activity));
+        if FEATURES.get().unwrap().emoji_by_name {
+            resolve_reaction(&ctx).await;
+        }
+        if FEATURES.get().unwrap().guild_locale {
+            apply_guild_locale(&ctx).await;
+        }
+
+        start_watchers(&mut *self.watchers.lock().await, || Watchers {
+            session: Some(tokio::spawn(session::session_loop())),
+            dota: Some(tokio::spawn(main_loop())),
+            steam_profile: Some(tokio::spawn(steam_profile::steam_profile_loop())),
+            library: Some(tokio::spawn(library::library_loop())),
+            achievements: Some(tokio::spawn(achievements::achievements_loop())),
+            live_scores: Some(tokio::spawn(live::live_score_loop())),
+            steam_group: Some(tokio::spawn(steam_group::steam_group_loop())),
+        })
+        .await;
+    }
+
+    async fn resume(&self, _ctx: Context, _event: ResumedEvent) {
+        // Nothing was lost, the watcher tasks just carry on.
+        println!("Gateway session resumed");
+    }
+}
+
+/// Switches to the localization matching the target guild's preferred locale.
+async fn apply_guild_locale(ctx: &Context) {
+    let guild = match GuildId::new(*TARGET_GUILD.get().unwrap()).to_partial_guild(&ctx.http).await
+    {
+        Ok(guild) => guild,
+        Err(why) => {
+            eprintln!("Couldn't fetch the guild locale: {why:?}");
+            return;
+        }
+    };
+    *GUILD_LOCALE.lock().unwrap() = Some(guild.preferred_locale.clone());
+    // Only the localization depends on the locale; features and routes stay as they are.
+    match load_localization() {
+        Ok(locals) => {
+            if LOCALIZATION.replace(locals) {
+                println!("Using the localization for guild locale {}", guild.preferred_locale);
+            }
+        }
+        Err(err) => eprintln!("Couldn't load the localization for the guild locale: {err}"),
+    }
+}
+
+/// Whether Discord rejected a reaction because the emoji doesn't exist.
+fn is_unknown_emoji(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref(),
+        Some(serenity::Error::Http(HttpError::UnsuccessfulRequest(response)))
+            if response.error.code == DISCORD_UNKNOWN_EMOJI
+    )
+}
+
+/// Reacts to one of the target's messages, unless unknown emojis made the bot
+/// give up on reacting.
+async fn react_to_target(sink: &dyn MessageSink, channel: ChannelId, message: MessageId) {
+    if *REACTION_FAILURES.lock().unwrap() >= REACTION_FAILURE_LIMIT {
+        return;
+    }
+    let resolved = RESOLVED_REACTION.lock().unwrap().clone();
+    let reaction = resolved.unwrap_or_else(|| REACTION.get().unwrap().clone());
+    match sink.react(channel, message, reaction).await {
+        Ok(()) => *REACTION_FAILURES.lock().unwrap() = 0,
+        Err(why) => {
+            eprintln!("Error reacting to message: {why:?}");
+            // Missing permissions or outages say nothing about the emoji itself.
+            if is_unknown_emoji(&why) {
+                record_reaction_failure();
+            }
+        }
+    }
+}
+
+/// Counts a reaction that failed on an unknown emoji. After
+/// `REACTION_FAILURE_LIMIT` such failures in a row the bot switches to
+/// `FALLBACK_REACTION`, or stops reacting when there is none or it fails as well.
+fn record_reaction_failure() {
+    let mut failures = REACTION_FAILURES.lock().unwrap();
+    *failures += 1;
+    if *failures != REACTION_FAILURE_LIMIT {
+        return;
+    }
+    let mut resolved = RESOLVED_REACTION.lock().unwrap();
+    match FALLBACK_REACTION.get().unwrap() {
+        Some(fallback) if resolved.as_ref() != Some(fallback) => {
+            eprintln!("Reacting failed {failures} times in a row, switching to {fallback}");
+            *resolved = Some(fallback.clone());
+            *failures = 0;
+        }
+        _ => eprintln!("Reacting failed {failures} times in a row, not reacting anymore"),
+    }
+}
+
+/// Looks the custom reaction emoji up by name among the target guild's emojis,
+/// so a re-uploaded emoji keeps working. The configured id stays as fallback.
+async fn resolve_reaction(ctx: &Context) {
+    let ReactionType::Custom { name: Some(name), .. } = REACTION.get().unwrap() else {
+        return;
+    };
+    let emojis = match GuildId::new(*TARGET_GUILD.get().unwrap()).emojis(&ctx.http).await {
+        Ok(emojis) => emojis,
+        Err(why) => {
+            eprintln!("Couldn't list guild emojis: {why:?}");
+            return;
+        }
+    };
+    match emoji_by_name(name, emojis) {
+        Some(reaction) => *RESOLVED_REACTION.lock().unwrap() = Some(reaction),
+        None => eprintln!("No emoji named {name} in the target guild, using the configured id"),
+    }
+}
+
+/// The reaction for the guild emoji called `name` among `emojis`.
+fn emoji_by_name(name: &str, emojis: Vec<Emoji>) -> Option<ReactionType> {
+    let emoji = emojis.into_iter().find(|emoji| emoji.name == name)?;
+    Some(ReactionType::Custom { animated: emoji.animated, id: emoji.id, name: Some(emoji.name) })
+}
+
+/// Reads `localization.json`, or with the `guild_locale` feature the file
+/// for the guild's locale (`localization.pt-BR.json`, then `localization.pt.json`)
+/// when one exists.
+fn load_localization() -> Result<Localization> {
+    let locale = GUILD_LOCALE.lock().unwrap().clone();
+    let locale = locale.filter(|_| FEATURES.get().is_some_and(|f| f.guild_locale));
+    let file = localization_file(locale.as_deref(), |file| Path::new(file).exists());
+    let body = std::fs::read_to_string(&file)
+        .map_err(|err| anyhow!("{file} file in the root folder: {err}"))?;
+    serde_json::from_str(&body).map_err(|err| anyhow!("Invalid {file}: {err}"))
+}
+
+/// The most specific localization file for `locale` that `exists`.
+fn localization_file(locale: Option<&str>, exists: impl Fn(&str) -> bool) -> String {
+    let mut files = Vec::new();
+    if let Some(locale) = locale {
+        files.push(format!("localization.{locale}.json"));
+        if let Some((language, _)) = locale.split_once('-') {
+            files.push(format!("localization.{language}.json"));
+        }
+    }
+    files.push("localization.json".to_string());
+    let found = files.iter().position(|file| exists(file));
+    files.swap_remove(found.unwrap_or(files.len() - 1))
+}
+
+fn load_features() -> Result<Features> {
+    Features::load(std::fs::read_to_string("features.json").ok().as_deref())
+        .map_err(|err| anyhow!("Invalid features: {err}"))
+}
+
+fn load_routes() -> Result<RoutingTable> {
+    match std::fs::read_to_string("routes.json") {
+        Ok(routes) => {
+            serde_json::from_str(&routes).map_err(|err| anyhow!("Invalid routes.json: {err}"))
+        }
+        Err(_) => Ok(RoutingTable::default()),
+    }
+}
+
+/// Reads a secret from the file named by `{name}_FILE` (as with Docker secrets),
+/// falling back to the `name` variable itself.
+fn env_secret(name: &str) -> Option<String> {
+    let file_var = format!("{name}_FILE");
+    match env::var(&file_var) {
+        Ok(path) => {
+            let secret = std::fs::read_to_string(&path)
+                .unwrap_or_else(|err| panic!("Couldn't read {file_var} at {path}: {err}"));
+            Some(secret.trim().to_string())
+        }
+        Err(_) => env::var(name).ok(),
+    }
+}
+
+/// The gateway intents the enabled features need, logging why each
+/// privileged one is requested. Decided at startup, so flipping the
+/// `disable_*` features with `/reload` only takes full effect after a restart.
+fn gateway_intents(features: &Features, locals: &Localization) -> GatewayIntents {
+    let mut intents = GatewayIntents::empty();
+    let watch_presence = !features.disable_presence;
+    if watch_presence {
+        println!("Requesting GUILD_PRESENCES to watch the target's presence");
+        intents |= GatewayIntents::GUILD_PRESENCES;
+    }
+    // Without the portal switch Discord refuses the connection, so only ask
+    // when member announcements are actually configured.
+    let member_announcements = locals.target_left.is_some() || locals.target_rejoined.is_some();
+    if watch_presence && member_announcements {
+        println!("Requesting GUILD_MEMBERS to notice the target leaving the server");
+        intents |= GatewayIntents::GUILD_MEMBERS;
+    }
+    // Message contents are never read, so MESSAGE_CONTENT isn't needed for either.
+    let track_channel = watch_presence && *ACTIVE_CHANNEL_WINDOW_SECS.get().unwrap() > 0;
+    if !features.disable_reactions || track_channel {
+        intents |= GatewayIntents::GUILD_MESSAGES | GatewayIntents::DIRECT_MESSAGES;
+    }
+    intents
+}
+
+/// Resolves on Ctrl+C, or on SIGTERM where available.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{ signal, SignalKind };
+        let mut terminate = signal(SignalKind::terminate()).expect("SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await.ok();
+}
+
+#[tokio::main]
+async fn main() {
+    dotenv::dotenv().ok();
+
+    let token = env_secret("DISCORD_TOKEN").expect("Expected a token in the environment");
+
+    set_env_num!(TARGET_GUILD);
+    set_env_num_list_or_default!(OUTPUT_CHANNEL);
+    assert!(
+        !OUTPUT_CHANNEL.get().unwrap().is_empty(),
+        "Expected OUTPUT_CHANNEL in the environment"
+    );
+    set_env_num!(TARGET_USER);
+    set_env_num!(TARGET_STEAMID32);
+    set_env_parse_or_default!(ALT_ACCOUNTS, AltAccounts::default());
+    set_env_parse_or_default!(TEAMMATE_USERS, TeammateUsers::default());
+    // `REACTION` takes `<:name:id>`, `<a:name:id>` or a plain emoji; the older
+    // `EMOJI_ID`/`EMOJI_NAME` pair still works for custom emoji.
+    let reaction = match env::var("REACTION") {
+        Ok(reaction) if !reaction.trim().is_empty() => reaction
+            .trim()
+            .parse()
+            .unwrap_or_else(|err| panic!("Invalid REACTION: {err}")),
+        _ => ReactionType::Custom {
+            animated: false,
+            id: EmojiId::new(
+                env::var("EMOJI_ID")
+                    .expect("Expected REACTION or EMOJI_ID in the environment")
+                    .parse()
+                    .expect("EMOJI_ID not a number"),
+            ),
+            name: Some(env::var("EMOJI_NAME").expect("Expected EMOJI_NAME in the environment")),
+        },
+    };
+    REACTION.set(reaction).unwrap();
+    let fallback = match env::var("FALLBACK_REACTION") {
+        Ok(reaction) if !reaction.trim().is_empty() => Some(
+            reaction
+                .trim()
+                .parse()
+                .unwrap_or_else(|err| panic!("Invalid FALLBACK_REACTION: {err}")),
+        ),
+        _ => None,
+    };
+    FALLBACK_REACTION.set(fallback).unwrap();
+    set_env_str_or_default!(MATCH_PREFIX);
+    set_env_str_or_default!(PRESENCE_PREFIX);
+    STEAM_API_KEY
+        .set(env_secret("STEAM_API_KEY").unwrap_or_default().parse().unwrap())
+        .unwrap();
+    EXTERNAL_WEBHOOK_URL
+        .set(env_secret("EXTERNAL_WEBHOOK_URL").unwrap_or_default())
+        .unwrap();
+    set_env_num_or_default!(MAX_MESSAGE_LENGTH, DISCORD_CONTENT_LIMIT);
+    set_env_num_list_or_default!(ADMIN_USERS);
+    set_env_num_or_default!(STOMP_MAX_MINUTES, 20);
+    set_env_num_or_default!(HEARTBREAKER_MIN_MINUTES, 60);
+    set_env_num_or_default!(HIGH_KILLS, 20);
+    set_env_num_or_default!(FIRST_BLOOD_MAX_SECS, 120);
+    set_env_num_or_default!(HERO_DUST_DAYS, 60);
+    set_env_num_or_default!(ACTIVE_CHANNEL_WINDOW_SECS, 0);
+    let opendota_base_url = env::var("OPENDOTA_BASE_URL")
+        .unwrap_or_else(|_| DEFAULT_OPENDOTA_BASE_URL.to_string());
+    match reqwest::Url::parse(&opendota_base_url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+        Ok(url) => panic!("Invalid OPENDOTA_BASE_URL: unsupported scheme {}", url.scheme()),
+        Err(err) => panic!("Invalid OPENDOTA_BASE_URL: {err}"),
+    }
+    OPENDOTA_BASE_URL
+        .set(opendota_base_url.trim_end_matches('/').to_string())
+        .unwrap();
+    set_env_num_or_default!(OPENDOTA_RATE_PER_MINUTE, 60);
+    OPENDOTA_LIMITER
+        .set(RateLimiter::new(*OPENDOTA_RATE_PER_MINUTE.get().unwrap()))
+        .unwrap();
+    set_env_parse_or_default!(MATCH_EMBED_COLORS, EmbedColorScheme::Outcome);
+    set_env_parse_or_default!(ANNOUNCE_RESULTS, ResultFilter::Both);
+    set_env_parse_or_default!(MATCH_SOURCE, MatchSource::OpenDota);
+    set_env_parse_or_default!(KDA_STYLE, KdaStyle::Comma);
+    set_env_num_list_or_default!(LONG_SESSION_HOURS);
+    set_env_num_list_or_default!(MATCH_MILESTONES);
+    set_env_num_or_default!(DOTA_IDLE_MINUTES, 0);
+    set_env_num_or_default!(FIRST_MATCH_GAP_HOURS, 6);
+    set_env_num_or_default!(MATCH_ANNOUNCE_DELAY_SECS, 0);
+    set_env_num_or_default!(GAME_REANNOUNCE_SECS, 60);
+    set_env_num_or_default!(HISTORY_CAP, 32);
+    if *HISTORY_CAP.get().unwrap() == 0 {
+        panic!("HISTORY_CAP must be at least 1");
+    }
+    set_env_num_or_default!(MATCH_LRU_CAP, 32);
+    if *MATCH_LRU_CAP.get().unwrap() == 0 {
+        panic!("MATCH_LRU_CAP must be at least 1");
+    }
+    set_env_num_or_default!(ANNOUNCE_EVERY_N_MATCHES, 1);
+    set_env_num_or_default!(LIVE_SCORE_INTERVAL_SECS, 120);
+    if *LIVE_SCORE_INTERVAL_SECS.get().unwrap() == 0 {
+        panic!("LIVE_SCORE_INTERVAL_SECS must be at least 1");
+    }
+    set_env_num_or_default!(LIVE_SCORE_MIN_CHANGE, 5);
+    set_env_num_list_or_default!(ALLOWED_ACTIVITY_APP_IDS);
+    STATE_FILE
+        .set(env::var("STATE_FILE").unwrap_or_else(|_| "state.json".to_string()).into())
+        .unwrap();
+    STATE
+        .set(StdMutex::new(PersistentState::load(STATE_FILE.get().unwrap())))
+        .unwrap();
+    SEEN_MATCHES_FILE
+        .set(
+            env::var("SEEN_MATCHES_FILE")
+                .unwrap_or_else(|_| "seen_matches.json".to_string())
+                .into(),
+        )
+        .unwrap();
+    SEEN_MATCHES
+        .set(StdMutex::new(SeenMatches::load(SEEN_MATCHES_FILE.get().unwrap())))
+        .unwrap();
+    LIBRARY_FILE
+        .set(env::var("LIBRARY_FILE").unwrap_or_else(|_| "library.json".to_string()).into())
+        .unwrap();
+    ACHIEVEMENTS_FILE
+        .set(
+            env::var("ACHIEVEMENTS_FILE")
+                .unwrap_or_else(|_| "achievements.json".to_string())
+                .into(),
+        )
+        .unwrap();
+    set_env_parse_or_default!(
+        STEAM_APP_IDS,
+        SteamApps(HashMap::from([(dota_presence::DOTA_ACTIVITY_NAME.to_string(), 570)]))
+    );
+    set_env_num_or_default!(STEAM_GROUP_ID, 0);
+    set_env_num_or_default!(STEAM_GROUP_MAX_MEMBERS, 100);
+
+    LOCALIZATION.set(load_localization().unwrap_or_else(|err| panic!("{err}"))).unwrap();
+    FEATURES.set(load_features().unwrap_or_else(|err| panic!("{err}"))).unwrap();
+    ROUTES.set(load_routes().unwrap_or_else(|err| panic!("{err}"))).unwrap();
+
+    let intents = gateway_intents(FEATURES.get().unwrap(), LOCALIZATION.get().unwrap());
+
+    let sink: Arc<dyn MessageSink> = Arc::new(HttpSink::new(Arc::new(Http::new(&token))));
+    let mut client = Client::builder(&token, intents)
+        .event_handler(Handler { watchers: Mutex::new(None), sink: sink.clone() })
+        .await
+        .expect("Successfull client creation");
+
+    let notifiers: Vec<Box<dyn Notifier>> =
+        vec![Box::new(DiscordNotifier::new(sink))];
+    if NOTIFIERS.set(notifiers).is_err() {
+        panic!("Couldn't set NOTIFIERS");
+    }
+
+    let shard_manager = client.shard_manager.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        if FEATURES.get().unwrap().announce_lifecycle {
+            let locals = LOCALIZATION.get().unwrap();
+            let text = locals.watcher_stopping.as_deref().unwrap_or("Watcher shutting down");
+            announce(Announcement::new(EventKind::Lifecycle, text.to_string())).await;
+        }
+        shard_manager.shutdown_all().await;
+    });
+
+    if let Err(why) = client.start().await {
+        eprintln!("Client error: {why:?}");
+    }
+}
+
+#[cfg(test)]
+static NOTIFIED: StdMutex<Vec<Announcement>> = StdMutex::new(Vec::new());
+
+/// Fills the globals the tests touch with a fixed configuration: matches go
+/// to channel 1 as embeds pinging role 5, party matches to channel 4,
+/// presence to channel 2 as plain text, lifecycle posts to channel 3.
+/// `notify` hands announcements to a `RecordingNotifier` collecting them in
+/// `NOTIFIED`.
+#[cfg(test)]
+fn init_test_globals() {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        OUTPUT_CHANNEL.set(vec![1]).unwrap();
+        MATCH_PREFIX.set(String::new()).unwrap();
+        PRESENCE_PREFIX.set("[activity]".to_string()).unwrap();
+        MAX_MESSAGE_LENGTH.set(DISCORD_CONTENT_LIMIT).unwrap();
+        ACTIVE_CHANNEL_WINDOW_SECS.set(0).unwrap();
+        FEATURES.set(Features { announce_lifecycle: true, ..Features::default() }).unwrap();
+        let routes = r#"{
+            "match": { "channel": 1, "embed": true, "mention": 5, "reaction": "🔥" },
+            "presence": { "channel": 2, "tts": false },
+            "lifecycle": { "channel": 3 },
+            "party_match": { "channel": 4, "embed": true }
+        }"#;
+        ROUTES.set(serde_json::from_str(routes).unwrap()).unwrap();
+        HISTORY_CAP.set(32).unwrap();
+        LOCALIZATION.set(test_localization()).unwrap();
+        let axe = Hero {
+            id: 2,
+            localized_name: "Axe".to_string(),
+            primary_attr: "str".to_string(),
+        };
+        HEROES.set(HashMap::from([(axe.id, axe)])).unwrap();
+        TARGET_STEAMID32.set(1000).unwrap();
+        TEAMMATE_USERS.set(TeammateUsers::default()).unwrap();
+        MATCH_EMBED_COLORS.set(EmbedColorScheme::Outcome).unwrap();
+        KDA_STYLE.set(KdaStyle::Slash).unwrap();
+        HIGH_KILLS.set(20).unwrap();
+        STOMP_MAX_MINUTES.set(20).unwrap();
+        HEARTBREAKER_MIN_MINUTES.set(60).unwrap();
+        REACTION.set(ReactionType::Unicode("🔥".to_string())).unwrap();
+        FALLBACK_REACTION.set(Some(ReactionType::Unicode("👍".to_string()))).unwrap();
+        let recorder = notifier::RecordingNotifier { sent: &NOTIFIED };
+        if NOTIFIERS.set(vec![Box::new(recorder)]).is_err() {
+            panic!("Couldn't set NOTIFIERS");
+        }
+    });
+}
+
+/// The English basics every localization file has, without optional templates.
+#[cfg(test)]
+fn test_localization() -> Localization {
+    serde_json::from_value(serde_json::json!({
+        "bot_activity": "Dota 2",
+        "plays": "plays",
+        "won": "won",
+        "lost": "lost",
+        "played_on": "Played on",
+        "with_score": "with a score of",
+        "match_duration": "The match lasted",
+        "minutes": "minutes",
+        "minute": "minute",
+        "target_name": "Target",
+        "offline": "went offline",
+        "idle": "is away",
+        "invisible": "is invisible",
+        "online": "is online",
+        "donotdisturb": "is busy",
+        "unknown": "is somewhere",
+        "using_phone": " on the phone",
+        "using_browser": " in the browser",
+        "using_computer": "",
+        "nicknames": { "7": "Seven" },
+        "back_in_action": "Back in action!",
+        "high_kills": "Carried hard with {kills} kills!",
+        "dusting_off": "Dusting off {hero} after {days} days!",
+        "solo_queue": "Solo queue.",
+        "party_queue": "In a party of {size}.",
+    }))
+    .unwrap()
+}
+
+/// Answers the first connection to a local port with the raw HTTP `response`
+/// and hands back the request it got, for tests of outbound calls.
+#[cfg(test)]
+fn mock_server(response: Vec<u8>) -> (String, std::sync::mpsc::Receiver<String>) {
+    use std::io::{ Read, Write };
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (requests, request) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            let read = stream.read(&mut buffer).unwrap();
+            if read == 0 {
+                break;
+            }
+            received.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&received);
+            let Some(head_end) = text.find("\r\n\r\n") else {
+                continue;
+            };
+            let length = text[..head_end]
+                .lines()
+                .find_map(|line| {
+                    line.to_lowercase().strip_prefix("content-length:")?.trim().parse().ok()
+                })
+                .unwrap_or(0);
+            if received.len() >= head_end + 4 + length {
+                break;
+            }
+        }
+        // The client may hang up early on a body it refuses, which is fine.
+        let _ = stream.write_all(&response);
+        let _ = requests.send(String::from_utf8_lossy(&received).into_owned());
+    });
+    (url, request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn wait_until_finished(handle: &Option<JoinHandle<()>>) {
+        while !handle.as_ref().unwrap().is_finished() {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn revive_keeps_running_tasks() {
+        let mut running = Some(tokio::spawn(std::future::pending::<()>()));
+        revive(&mut running, "running", || async { unreachable!() }).await;
+        assert!(running.is_some_and(|handle| !handle.is_finished()));
+    }
+
+    #[tokio::test]
+    async fn revive_drops_returned_tasks_without_polling_them_again() {
+        let mut returned = Some(tokio::spawn(async {}));
+        wait_until_finished(&returned).await;
+        // A second reconnect must not await the finished handle again.
+        for _ in 0..2 {
+            revive(&mut returned, "returned", || async { unreachable!() }).await;
+            assert!(returned.is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn revive_restarts_panicked_tasks() {
+        let mut panicked = Some(tokio::spawn(async { panic!("loop died") }));
+        wait_until_finished(&panicked).await;
+        revive(&mut panicked, "panicked", std::future::pending::<()>).await;
+        assert!(panicked.is_some_and(|handle| !handle.is_finished()));
+    }
+
+    #[test]
+    fn format_number_abbreviates_thousands_and_millions() {
+        let cases = [
+            (999, "999"),
+            (1000, "1k"),
+            (18_432, "18.4k"),
+            (999_949, "999.9k"),
+            (999_950, "1M"),
+            (999_999, "1M"),
+            (1_250_000, "1.3M"),
+            (-1500, "-1.5k"),
+        ];
+        for (n, expected) in cases {
+            assert_eq!(format_number(n, true), expected, "{n}");
+        }
+    }
+
+    #[test]
+    fn format_number_keeps_raw_numbers() {
+        for n in [999, 1000, 18_432, 1_250_000] {
+            assert_eq!(format_number(n, false), n.to_string());
+        }
+    }
+
+    #[tokio::test]
+    async fn notify_hands_announcements_to_the_notifiers_and_the_history() {
+        init_test_globals();
+        notify(Announcement::new(EventKind::Lifecycle, "notify test".to_string())).await;
+
+        let notified = NOTIFIED.lock().unwrap();
+        assert!(notified.iter().any(|sent| sent.text == "notify test"));
+        let history = ANNOUNCEMENT_HISTORY.lock().unwrap();
+        assert!(history.iter().any(|record| record.text == "notify test"));
+    }
+
+    #[test]
+    fn teammate_users_skip_zero_user_ids() {
+        let users: TeammateUsers = "11:0, 22:33,44:x".parse().unwrap();
+        assert_eq!(users.0.len(), 1);
+        assert_eq!(users.0[&22], UserId::new(33));
+    }
+
+    #[test]
+    fn format_kda_follows_the_style() {
+        let m = MatchData {
+            kills: Some(12),
+            deaths: Some(3),
+            assists: Some(18),
+            ..Default::default()
+        };
+        assert_eq!(format_kda(&m, KdaStyle::Comma), "12, 3, 18");
+        assert_eq!(format_kda(&m, KdaStyle::Slash), "12/3/18");
+        assert_eq!(format_kda(&m, KdaStyle::Emoji), "\u{2694}\u{fe0f}12/\u{1f480}3/\u{1f91d}18");
+    }
+
+    #[test]
+    fn clamp_message_keeps_short_messages() {
+        assert_eq!(clamp_message("abc\u{1f44d}\u{1f3fd}", 5), "abc\u{1f44d}\u{1f3fd}");
+    }
+
+    #[test]
+    fn clamp_message_never_splits_emoji() {
+        // A thumbs up with a skin tone is two chars but one grapheme.
+        let text = "abc\u{1f44d}\u{1f3fd}def";
+        assert_eq!(clamp_message(text, 6), "abc\u{1f44d}\u{1f3fd}\u{2026}");
+        assert_eq!(clamp_message(text, 5), "abc\u{2026}");
+        // So is a flag made of two regional indicators.
+        assert_eq!(clamp_message("ab\u{1f1eb}\u{1f1ee}cd", 4), "ab\u{2026}");
+    }
+
+    #[test]
+    fn clamp_message_never_splits_combining_marks() {
+        let text = "cafe\u{301} au lait";
+        assert_eq!(clamp_message(text, 6), "cafe\u{301}\u{2026}");
+        assert_eq!(clamp_message(text, 5), "caf\u{2026}");
+    }
+
+    #[test]
+    fn min_interval_holds_back_only_the_same_kind() {
+        let mut last_sent = BTreeMap::new();
+        let hour = Duration::from_secs(3600);
+        assert!(!inside_min_interval(&mut last_sent, EventKind::Match, hour));
+        assert!(inside_min_interval(&mut last_sent, EventKind::Match, hour));
+        assert!(!inside_min_interval(&mut last_sent, EventKind::Presence, hour));
+        // Without a minimum interval nothing is held back.
+        assert!(!inside_min_interval(&mut last_sent, EventKind::Match, Duration::ZERO));
+    }
+
+
+    #[test]
+    fn phrase_picks_one_of_its_options() {
+        let phrase: Phrase = serde_json::from_str(r#"["won", "took it", "crushed it"]"#).unwrap();
+        let Phrase::Many(options) = &phrase else {
+            panic!("expected several phrasings, got {phrase:?}");
+        };
+        for _ in 0..20 {
+            let picked = phrase.pick();
+            assert!(options.iter().any(|option| option == picked), "{picked:?}");
+        }
+        assert_eq!(Phrase::One("won".to_string()).pick(), "won");
+        assert_eq!(Phrase::Many(Vec::new()).pick(), "");
+    }
+
+
+    #[test]
+    fn secrets_are_read_from_their_file_first() {
+        let path = std::env::temp_dir().join(format!("dotawatcher-secret-{}", std::process::id()));
+        std::fs::write(&path, "https://example.com/hook?token=abc\n").unwrap();
+        env::set_var("DOTAWATCHER_TEST_WEBHOOK_FILE", &path);
+        env::set_var("DOTAWATCHER_TEST_WEBHOOK", "https://example.com/plain");
+        env::set_var("DOTAWATCHER_TEST_PLAIN", "https://example.com/plain");
+        let from_file = env_secret("DOTAWATCHER_TEST_WEBHOOK");
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(from_file.as_deref(), Some("https://example.com/hook?token=abc"));
+        assert_eq!(
+            env_secret("DOTAWATCHER_TEST_PLAIN").as_deref(),
+            Some("https://example.com/plain")
+        );
+        assert_eq!(env_secret("DOTAWATCHER_TEST_UNSET"), None);
+    }
+
+
+    #[tokio::test]
+    async fn oversized_bodies_are_refused() {
+        let mut response = b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n".to_vec();
+        response.resize(response.len() + MAX_BODY_BYTES + 1, b'x');
+        let (url, _) = mock_server(response);
+        let response = http_client().get(&url).send().await.unwrap();
+        let err = read_capped(&url, response).await.unwrap_err();
+        assert!(matches!(err, FetchError::Api(message) if message.contains("exceeds")));
+
+        let (url, _) = mock_server(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok".to_vec());
+        let response = http_client().get(&url).send().await.unwrap();
+        assert_eq!(read_capped(&url, response).await.unwrap(), "ok");
+    }
+
+
+    #[test]
+    fn matches_without_a_result_are_given_up_on() {
+        let mut waiting = None;
+        for _ in 1..RESULT_WAIT_POLLS {
+            assert!(!result_wait_over(&mut waiting, 7));
+        }
+        assert!(result_wait_over(&mut waiting, 7));
+        // A newer match starts its own count.
+        assert!(!result_wait_over(&mut waiting, 8));
+        assert_eq!(waiting, Some((8, 1)));
+    }
+
+
+    #[test]
+    fn relaunches_keep_a_game_quiet_until_it_stays_gone() {
+        let window = Duration::from_secs(60);
+        let left = Instant::now();
+        let mut last_seen = BTreeMap::from([("Dota 2".to_string(), left)]);
+        let after = |secs| left + Duration::from_secs(secs);
+
+        assert!(seen_within(&mut last_seen, "Dota 2", after(50), window));
+        // The window now runs from the relaunch at 50s, not from leaving at 0s.
+        assert!(seen_within(&mut last_seen, "Dota 2", after(100), window));
+        assert!(!seen_within(&mut last_seen, "Dota 2", after(200), window));
+        assert!(!seen_within(&mut last_seen, "Terraria", after(200), window));
+    }
+
+
+    #[test]
+    fn steam_only_setups_need_no_gateway_intents() {
+        init_test_globals();
+        let features = Features {
+            disable_presence: true,
+            disable_reactions: true,
+            ..Features::default()
+        };
+        let locals = Localization {
+            target_left: Some("{target_name} left".to_string()),
+            ..test_localization()
+        };
+        assert_eq!(gateway_intents(&features, &locals), GatewayIntents::empty());
+
+        let features = Features { disable_presence: true, ..Features::default() };
+        assert_eq!(
+            gateway_intents(&features, &locals),
+            GatewayIntents::GUILD_MESSAGES | GatewayIntents::DIRECT_MESSAGES
+        );
+    }
+
+
+    /// The error serenity returns when Discord doesn't know a reaction's emoji.
+    async fn unknown_emoji_error() -> anyhow::Error {
+        let body = r#"{"code": 10014, "message": "Unknown Emoji"}"#;
+        let response = format!(
+            "HTTP/1.1 400 Bad Request\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let (url, _) = mock_server(response.into_bytes());
+        let response = http_client().put(&url).send().await.unwrap();
+        let response =
+            serenity::http::ErrorResponse::from_response(response, reqwest::Method::PUT).await;
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response)).into()
+    }
+
+    #[tokio::test]
+    async fn unknown_emojis_switch_to_the_fallback_then_stop_reactions() {
+        init_test_globals();
+        let mut errors = Vec::new();
+        for _ in 0..2 * REACTION_FAILURE_LIMIT {
+            errors.push(unknown_emoji_error().await);
+        }
+        let sink = notifier::RecordingSink {
+            reaction_errors: StdMutex::new(errors),
+            ..Default::default()
+        };
+        for message in 1..=2 * REACTION_FAILURE_LIMIT + 1 {
+            react_to_target(&sink, ChannelId::new(4), MessageId::new(message.into())).await;
+        }
+
+        let reactions: Vec<_> = sink
+            .calls()
+            .into_iter()
+            .map(|call| match call {
+                notifier::SinkCall::React { reaction, .. } => reaction.to_string(),
+                call => panic!("expected only reactions, got {call:?}"),
+            })
+            .collect();
+        let limit = REACTION_FAILURE_LIMIT as usize;
+        assert_eq!(reactions, [vec!["🔥"; limit], vec!["👍"; limit]].concat());
+    }
+
+
+    #[tokio::test]
+    async fn webhook_posts_the_match_as_json() {
+        init_test_globals();
+        let (url, request) = mock_server(b"HTTP/1.1 204 No Content\r\n\r\n".to_vec());
+        let last = MatchData {
+            match_id: 7_000_000_001,
+            player_slot: 130,
+            hero_id: 2,
+            radiant_win: Some(false),
+            kills: Some(9),
+            deaths: Some(2),
+            assists: Some(11),
+            alt: Some(AltAccount { id: 2000, label: "smurf".to_string() }),
+            ..MatchData::default()
+        };
+        send_match_webhook(&url, &last).await;
+
+        let request = request.recv().unwrap();
+        assert!(request.starts_with("POST / HTTP/1.1"), "{request}");
+        let (_, body) = request.split_once("\r\n\r\n").unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(body).unwrap(),
+            serde_json::json!({
+                "match_id": 7_000_000_001_i64,
+                "player": 2000,
+                "hero": "Axe",
+                "win": true,
+                "kills": 9,
+                "deaths": 2,
+                "assists": 11,
+            })
+        );
+    }
+
+
+    #[tokio::test]
+    async fn only_the_first_ready_starts_and_announces_the_watchers() {
+        init_test_globals();
+        let pending = || Some(tokio::spawn(std::future::pending::<()>()));
+        let mut watchers = None;
+        start_watchers(&mut watchers, || Watchers {
+            session: pending(),
+            dota: pending(),
+            steam_profile: pending(),
+            library: pending(),
+            achievements: pending(),
+            live_scores: pending(),
+            steam_group: pending(),
+        })
+        .await;
+        start_watchers(&mut watchers, || unreachable!("watchers started twice")).await;
+
+        let started = NOTIFIED
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|sent| sent.kind == EventKind::Lifecycle && sent.text == "Watcher started")
+            .count();
+        assert_eq!(started, 1);
+        assert!(watchers.is_some_and(|watchers| !watchers.dota.unwrap().is_finished()));
+    }
+
+
+    #[test]
+    fn guild_locale_picks_the_most_specific_localization() {
+        let present = ["localization.json", "localization.ru.json", "localization.pt.json"];
+        let exists = |file: &str| present.contains(&file);
+        assert_eq!(localization_file(Some("ru"), exists), "localization.ru.json");
+        assert_eq!(localization_file(Some("pt-BR"), exists), "localization.pt.json");
+        assert_eq!(localization_file(Some("de"), exists), "localization.json");
+        assert_eq!(localization_file(None, exists), "localization.json");
+        // A missing default is still the file the error names.
+        assert_eq!(localization_file(Some("de"), |_| false), "localization.json");
+    }
+
+
+    #[test]
+    fn alt_account_matches_name_the_alt() {
+        init_test_globals();
+        let last = MatchData {
+            hero_id: 2,
+            radiant_win: Some(true),
+            duration: Some(35 * 60),
+            kills: Some(5),
+            deaths: Some(4),
+            assists: Some(9),
+            alt: Some(AltAccount { id: 2000, label: "smurf".to_string() }),
+            ..MatchData::default()
+        };
+        assert_eq!(
+            format_match(&last, "won"),
+            "Target won. Played on Axe with a score of 5/4/9. The match lasted 35 minutes. \
+             (on their alt smurf)"
+        );
+    }
+
+
+    #[tokio::test]
+    async fn batched_matches_go_out_as_one_announcement() {
+        init_test_globals();
+        let game = |match_id, radiant_win, kills| MatchData {
+            match_id,
+            hero_id: 2,
+            radiant_win: Some(radiant_win),
+            kills: Some(kills),
+            deaths: Some(1),
+            assists: Some(2),
+            ..MatchData::default()
+        };
+        let batch = [game(11, true, 3), game(12, false, 4), game(13, true, 5)];
+        announce_batch(&batch).await;
+
+        let sent: Vec<_> = NOTIFIED
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|sent| sent.text.starts_with("Target's last 3 matches"))
+            .map(|sent| sent.text.clone())
+            .collect();
+        assert_eq!(
+            sent,
+            ["Target's last 3 matches: 2W-1L\nAxe: won, 3/1/2\nAxe: lost, 4/1/2\nAxe: won, 5/1/2"]
+        );
+    }
+
+    #[test]
+    fn durations_use_the_singular_only_for_one() {
+        assert_eq!(pluralize(1, "minute", "minutes"), "minute");
+        assert_eq!(pluralize(0, "minute", "minutes"), "minutes");
+        assert_eq!(pluralize(2, "minute", "minutes"), "minutes");
+        assert_eq!(pluralize(21, "minute", "minutes"), "minutes");
+
+        init_test_globals();
+        let last = MatchData { hero_id: 2, duration: Some(60), ..MatchData::default() };
+        assert!(format_match(&last, "lost").ends_with("The match lasted 1 minute."));
+    }
+
+
+    #[test]
+    fn short_wins_are_stomps_and_long_losses_heartbreakers() {
+        init_test_globals();
+        let game = |radiant_win, minutes: i64, kills, deaths| MatchData {
+            radiant_win: Some(radiant_win),
+            duration: Some(minutes * 60),
+            kills: Some(kills),
+            deaths: Some(deaths),
+            ..MatchData::default()
+        };
+        assert_eq!(classify_match(&game(true, 18, 10, 2)), MatchFlavor::Stomp);
+        // Carried through a quick win while feeding isn't a stomp.
+        assert_eq!(classify_match(&game(true, 18, 1, 9)), MatchFlavor::Regular);
+        assert_eq!(classify_match(&game(true, 40, 10, 2)), MatchFlavor::Regular);
+        assert_eq!(classify_match(&game(false, 65, 10, 8)), MatchFlavor::Heartbreaker);
+        assert_eq!(classify_match(&game(false, 30, 10, 8)), MatchFlavor::Regular);
+    }
+
+
+    /// An activity as the gateway sends it, from the fields a test cares about.
+    fn activity(mut value: serde_json::Value) -> Activity {
+        value["created_at"] = 0.into();
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn compact_activity_messages_fit_on_one_line() {
+        init_test_globals();
+        let dota = activity(serde_json::json!({
+            "name": "Dota 2",
+            "type": 0,
+            "details": "Ranked: Axe",
+            "assets": { "large_text": "Axe", "small_text": "Level 12" },
+        }));
+        assert_eq!(
+            activity_message("Target is online", &dota, true),
+            "Target is online plays Dota 2, Ranked: Axe"
+        );
+        assert_eq!(
+            activity_message("Target is online", &dota, false),
+            "Target is online plays Dota 2\nRanked: Axe\nAxe\nLevel 12"
+        );
+    }
+
+
+    #[test]
+    fn channel_stays_active_for_the_window_after_a_message() {
+        let window = Duration::from_secs(120);
+        let last = Instant::now();
+        let after = |secs| last + Duration::from_secs(secs);
+        assert_eq!(active_remaining(last, window, after(30)), Some(Duration::from_secs(90)));
+        assert_eq!(active_remaining(last, window, after(120)), None);
+        assert_eq!(active_remaining(last, window, after(300)), None);
+        // A zero window, the default, never counts the channel as active.
+        assert_eq!(active_remaining(last, Duration::ZERO, last), None);
+    }
+
+
+    #[test]
+    fn embeds_are_colored_by_the_hero_attribute() {
+        assert_eq!(attribute_color("str"), Colour::RED);
+        assert_eq!(attribute_color("agi"), Colour::DARK_GREEN);
+        assert_eq!(attribute_color("int"), Colour::BLUE);
+        assert_eq!(attribute_color("all"), Colour::PURPLE);
+        assert_eq!(attribute_color(""), Colour::default());
+        assert_eq!("attribute".parse::<EmbedColorScheme>().unwrap(), EmbedColorScheme::Attribute);
+        assert!("hero".parse::<EmbedColorScheme>().is_err());
+    }
+
+
+    #[test]
+    fn turbo_and_ranked_matches_pick_their_own_template() {
+        let game = |game_mode, lobby_type| MatchData {
+            game_mode,
+            lobby_type,
+            ..MatchData::default()
+        };
+        assert_eq!(game(GAME_MODE_TURBO, 0).mode_name(), Some("turbo"));
+        assert_eq!(game(GAME_MODE_TURBO, LOBBY_TYPE_RANKED).mode_name(), Some("turbo"));
+        assert_eq!(game(22, LOBBY_TYPE_RANKED).mode_name(), Some("ranked"));
+        assert_eq!(game(22, 0).mode_name(), None);
+
+        let template = "{target_name} {result} a turbo on {hero}, {result} again";
+        let values = [("target_name", "Target"), ("result", "won"), ("hero", "Axe")];
+        let rendered = render_template(template, &values);
+        assert_eq!(rendered, "Target won a turbo on Axe, won again");
+    }
+
+
+    #[test]
+    fn result_filter_lets_through_only_the_chosen_outcomes() {
+        let wins: ResultFilter = "wins".parse().unwrap();
+        assert!(wins.allows(true) && !wins.allows(false));
+        let losses: ResultFilter = "losses".parse().unwrap();
+        assert!(!losses.allows(true) && losses.allows(false));
+        let both: ResultFilter = "both".parse().unwrap();
+        assert!(both.allows(true) && both.allows(false));
+        assert!("draws".parse::<ResultFilter>().is_err());
+    }
+
+
+    #[test]
+    fn poll_stats_count_errors_until_the_next_success() {
+        let mut stats = PollStats::new();
+        stats.record_failure();
+        stats.record_failure();
+        assert_eq!((stats.consecutive_errors, stats.total_errors), (2, 2));
+        assert!(stats.last_success.is_none());
+
+        stats.record_success();
+        stats.record_failure();
+        assert_eq!((stats.consecutive_errors, stats.total_errors), (1, 3));
+        assert!(stats.last_success.is_some());
+    }
+
+
+    #[test]
+    fn only_allowed_applications_are_announced() {
+        let dota = activity(serde_json::json!({
+            "name": "Dota 2",
+            "type": 0,
+            "application_id": "356875570916753438",
+        }));
+        let custom = activity(serde_json::json!({ "name": "Custom Status", "type": 4 }));
+        let allowed = [356_875_570_916_753_438];
+
+        assert!(app_id_allowed(&dota, &allowed, false));
+        assert!(!app_id_allowed(&dota, &[1], false));
+        assert!(!app_id_allowed(&custom, &allowed, false));
+        assert!(app_id_allowed(&custom, &allowed, true));
+        // Without a list everything is allowed.
+        assert!(app_id_allowed(&custom, &[], false));
+    }
+
+
+    #[test]
+    fn routes_take_one_channel_or_several() {
+        init_test_globals();
+        let route = |json| serde_json::from_str::<AnnouncementRoute>(json).unwrap().channels;
+        assert_eq!(route(r#"{ "channel": 7 }"#), [ChannelId::new(7)]);
+        assert_eq!(route(r#"{ "channels": [7, 8] }"#), [ChannelId::new(7), ChannelId::new(8)]);
+        // Without any, the route falls back to every `OUTPUT_CHANNEL`.
+        assert_eq!(route("{}"), [ChannelId::new(1)]);
+    }
+
+
+    #[test]
+    fn channels_receive_only_the_kinds_they_subscribe_to() {
+        let table: RoutingTable = serde_json::from_str(
+            r#"{ "subscriptions": { "7": ["match"], "8": ["match", "presence"] } }"#,
+        )
+        .unwrap();
+        let (seven, eight, nine) = (ChannelId::new(7), ChannelId::new(8), ChannelId::new(9));
+        assert!(table.subscribed(seven, EventKind::Match));
+        assert!(!table.subscribed(seven, EventKind::Presence));
+        assert!(table.subscribed(eight, EventKind::Presence));
+        assert!(!table.subscribed(eight, EventKind::Lifecycle));
+        // Channels without subscriptions take everything.
+        assert!(table.subscribed(nine, EventKind::Lifecycle));
+    }
+
+
+    #[test]
+    fn empty_match_lists_and_private_profiles_parse() {
+        let matches: Response<MatchData> = serde_json::from_str("[]").unwrap();
+        assert!(matches.items.is_empty());
+
+        let player = |json| serde_json::from_str::<PlayerData>(json).unwrap().history_hidden();
+        assert!(player(r#"{ "profile": { "fh_unavailable": true } }"#));
+        assert!(!player(r#"{ "profile": { "fh_unavailable": false } }"#));
+        assert!(!player(r#"{ "profile": null }"#));
+        assert!(!player("{}"));
+    }
+
+
+    #[test]
+    fn status_transitions_are_looked_up_by_both_statuses() {
+        let transitions = HashMap::from([
+            ("offline->online".to_string(), "{target_name} is back".to_string()),
+            ("online->idle".to_string(), "{target_name} went afk".to_string()),
+        ]);
+        let template = |from, to| transition_template(&transitions, from, to).cloned();
+        assert_eq!(
+            template(OnlineStatus::Offline, OnlineStatus::Online).as_deref(),
+            Some("{target_name} is back")
+        );
+        assert_eq!(
+            template(OnlineStatus::Online, OnlineStatus::Idle).as_deref(),
+            Some("{target_name} went afk")
+        );
+        assert_eq!(template(OnlineStatus::Idle, OnlineStatus::Online), None);
+    }
+
+
+    #[test]
+    fn party_members_are_named_by_nickname_then_persona_then_id() {
+        init_test_globals();
+        let full_match: FullMatch = serde_json::from_value(serde_json::json!({
+            "players": [
+                { "account_id": 1000, "party_id": 1, "personaname": "target" },
+                { "account_id": 7, "party_id": 1, "personaname": "seven" },
+                { "account_id": 8, "party_id": 1, "personaname": "eight" },
+                { "account_id": 9, "party_id": 1 },
+                { "party_id": 1 },
+                { "account_id": 10, "party_id": 2, "personaname": "stranger" },
+            ]
+        }))
+        .unwrap();
+        assert_eq!(full_match.party_members(1000), ["Seven", "eight", "9"]);
+        assert!(full_match.party_members(4242).is_empty());
+
+        let last = MatchData { party: vec!["Seven".to_string()], ..MatchData::default() };
+        assert!(format_match(&last, "won").ends_with("\nParty: Seven."));
+    }
+
+
+    #[test]
+    fn reactions_parse_custom_animated_and_unicode_emoji() {
+        let parse = |value: serde_json::Value| parse_reaction(value).unwrap();
+        assert_eq!(
+            parse(serde_json::json!("<:dota:42>")),
+            Some(ReactionType::Custom {
+                animated: false,
+                id: EmojiId::new(42),
+                name: Some("dota".to_string()),
+            })
+        );
+        assert_eq!(
+            parse(serde_json::json!(" <a:party:43> ")),
+            Some(ReactionType::Custom {
+                animated: true,
+                id: EmojiId::new(43),
+                name: Some("party".to_string()),
+            })
+        );
+        assert_eq!(
+            parse(serde_json::json!("🔥")),
+            Some(ReactionType::Unicode("🔥".to_string()))
+        );
+        assert_eq!(parse(serde_json::Value::Null), None);
+    }
+
+
+    #[test]
+    fn passive_mode_holds_back_automatic_posts() {
+        let now = Instant::now();
+        assert!(paused(true, None, now));
+        assert!(!paused(false, None, now));
+        assert!(paused(true, Some(now - Duration::from_secs(1)), now));
+    }
+
+
+    #[test]
+    fn medal_emoji_is_picked_by_rank_bracket() {
+        assert_eq!(rank_medal(11), Some("herald"));
+        assert_eq!(rank_medal(54), Some("legend"));
+        assert_eq!(rank_medal(80), Some("immortal"));
+        assert_eq!(rank_medal(5), None);
+        assert_eq!(rank_medal(95), None);
+
+        let medal_emoji = HashMap::from([
+            ("herald".to_string(), "🥉".to_string()),
+            ("immortal".to_string(), "👑".to_string()),
+        ]);
+        assert_eq!(with_medal_emoji("Target", Some(13), &medal_emoji), "🥉 Target");
+        assert_eq!(with_medal_emoji("Target", Some(80), &medal_emoji), "👑 Target");
+        assert_eq!(with_medal_emoji("Target", Some(54), &medal_emoji), "Target");
+        assert_eq!(with_medal_emoji("Target", None, &medal_emoji), "Target");
+    }
+
+
+    #[test]
+    fn only_long_gaps_between_matches_get_the_back_in_action_flavor() {
+        init_test_globals();
+        let hour = 3600;
+        assert!(!after_break(Some(0), Some(2 * hour), 6));
+        assert!(after_break(Some(0), Some(7 * hour), 6));
+        assert!(!after_break(Some(0), Some(7 * hour), 0));
+        assert!(!after_break(None, Some(7 * hour), 6));
+
+        let back = MatchData { after_break: true, ..MatchData::default() };
+        assert!(format_match(&back, "won").contains("Back in action!"));
+        assert!(!format_match(&MatchData::default(), "won").contains("Back in action!"));
+    }
+
+
+    #[test]
+    fn activity_text_is_cleaned_to_one_line() {
+        assert_eq!(
+            sanitize_activity_text("  Ranked\n\tAll Pick\u{7}  —  Legend\r\n"),
+            "Ranked All Pick — Legend"
+        );
+        assert_eq!(sanitize_activity_text("Anti-Mage: 5/1/3"), "Anti-Mage: 5/1/3");
+        assert_eq!(sanitize_activity_text("\n\u{0}\n"), "");
+    }
+
+
+    #[test]
+    fn high_kill_matches_get_the_carried_line() {
+        init_test_globals();
+        let with_kills = |kills| MatchData { kills: Some(kills), ..MatchData::default() };
+        assert!(format_match(&with_kills(24), "won").contains("Carried hard with 24 kills!"));
+        assert!(format_match(&with_kills(20), "won").contains("Carried hard with 20 kills!"));
+        assert!(!format_match(&with_kills(8), "won").contains("Carried hard"));
+    }
+
+
+    #[test]
+    fn teams_are_split_by_side_around_the_target() {
+        init_test_globals();
+        let full_match: FullMatch = serde_json::from_value(serde_json::json!({
+            "players": [
+                { "player_slot": 0, "hero_id": 1 },
+                { "player_slot": 1, "hero_id": 2 },
+                { "player_slot": 2, "hero_id": 3 },
+                { "player_slot": 128, "hero_id": 4 },
+                { "player_slot": 129, "hero_id": 5 },
+            ]
+        }))
+        .unwrap();
+        let (allies, enemies) = full_match.teams(0);
+        assert_eq!(allies, ["Axe", "3"]);
+        assert_eq!(enemies, ["4", "5"]);
+
+        let last = MatchData { teams: Some((allies, enemies)), ..MatchData::default() };
+        assert!(format_match(&last, "won").ends_with("\nWith Axe, 3 vs 4, 5."));
+
+        let many = vec!["Axe".to_string(); 200];
+        let last = MatchData { teams: Some((many.clone(), many)), ..MatchData::default() };
+        let content = format_match(&last, "won");
+        let line = content.lines().last().unwrap();
+        assert_eq!(line.chars().count(), TEAMS_LINE_LIMIT);
+        assert!(line.ends_with('\u{2026}'));
+    }
+
+
+    #[test]
+    fn games_are_picked_over_a_custom_status_listed_first() {
+        let custom = activity(serde_json::json!({
+            "name": "Custom Status",
+            "type": 4,
+            "state": "gg",
+        }));
+        let listening = activity(serde_json::json!({ "name": "Spotify", "type": 2 }));
+        let dota = activity(serde_json::json!({ "name": "Dota 2", "type": 0 }));
+
+        let activities = [custom.clone(), listening.clone(), dota];
+        assert_eq!(primary_activity(&activities).unwrap().name, "Dota 2");
+        let activities = [custom.clone(), listening];
+        assert_eq!(primary_activity(&activities).unwrap().name, "Spotify");
+        assert_eq!(primary_activity(&[custom]).unwrap().name, "Custom Status");
+        assert!(primary_activity(&[]).is_none());
+    }
+
+
+    #[test]
+    fn logoff_summary_counts_the_session_and_picks_the_best_hero() {
+        init_test_globals();
+        let played = |hero_id, radiant_win, kills| MatchData {
+            hero_id,
+            radiant_win: Some(radiant_win),
+            kills: Some(kills),
+            deaths: Some(2),
+            assists: Some(4),
+            ..MatchData::default()
+        };
+        let session = [played(1, true, 3), played(2, true, 15), played(3, false, 1)];
+        let summary = logoff_summary(&session).unwrap();
+        assert_eq!(summary.text, "Target played 3 Dota matches: 2W-1L, best hero Axe.");
+        assert!(logoff_summary(&[]).is_none());
+    }
+
+
+    #[test]
+    fn custom_status_changes_are_announced_once() {
+        let dota = activity(serde_json::json!({ "name": "Dota 2", "type": 0, "state": "Ranked" }));
+        let custom = |state: &str| {
+            activity(serde_json::json!({ "name": "Custom Status", "type": 4, "state": state }))
+        };
+        assert_eq!(custom_status_text(std::slice::from_ref(&dota)), None);
+        assert_eq!(custom_status_text(&[dota, custom("gg")]).as_deref(), Some("gg"));
+
+        let mut last = None;
+        assert!(record_custom_status(&mut last, &Some("gg".to_string())));
+        assert!(!record_custom_status(&mut last, &Some("gg".to_string())));
+        assert!(record_custom_status(&mut last, &Some("brb".to_string())));
+        assert!(record_custom_status(&mut last, &None));
+        assert_eq!(last, None);
+    }
+
+
+    #[test]
+    fn first_run_seeds_silently_unless_asked_to_announce() {
+        assert!(!announces_new_match(true, false, false));
+        assert!(announces_new_match(true, false, true));
+        assert!(announces_new_match(false, false, false));
+        assert!(!announces_new_match(false, true, true));
+    }
+
+
+    #[test]
+    fn reaction_emoji_is_resolved_by_name() {
+        let emoji = |id: u64, name: &str, animated: bool| -> Emoji {
+            serde_json::from_value(serde_json::json!({
+                "id": id.to_string(),
+                "name": name,
+                "animated": animated,
+                "available": true,
+                "managed": false,
+                "require_colons": true,
+                "roles": [],
+            }))
+            .unwrap()
+        };
+        let emojis = vec![emoji(1, "pog", false), emoji(2, "dota", true)];
+        assert_eq!(
+            emoji_by_name("dota", emojis.clone()),
+            Some(ReactionType::Custom {
+                animated: true,
+                id: EmojiId::new(2),
+                name: Some("dota".to_string()),
+            })
+        );
+        assert_eq!(emoji_by_name("gone", emojis), None);
+    }
+
+
+    #[tokio::test]
+    async fn delayed_matches_are_announced_once_the_delay_is_over() {
+        init_test_globals();
+        // Told apart from other tests' posts by their kill counts.
+        let announced = |kills: i64| {
+            NOTIFIED
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|sent| sent.text.contains(&format!("{kills}/0/0")))
+        };
+        let last = MatchData { kills: Some(4242), hero_id: 2, ..MatchData::default() };
+        let delayed = schedule_match_announcement(last, Duration::from_millis(50)).await;
+        assert!(!announced(4242));
+        delayed.unwrap().await.unwrap();
+        assert!(announced(4242));
+
+        let last = MatchData { kills: Some(4343), hero_id: 2, ..MatchData::default() };
+        assert!(schedule_match_announcement(last, Duration::ZERO).await.is_none());
+        assert!(announced(4343));
+    }
+
+
+    #[test]
+    fn leaving_and_rejoining_the_guild_toggle_watching_once() {
+        let mut target_left = false;
+        assert!(!set_target_left(&mut target_left, false));
+        assert!(set_target_left(&mut target_left, true));
+        assert!(target_left);
+        assert!(!set_target_left(&mut target_left, true));
+        assert!(set_target_left(&mut target_left, false));
+        assert!(!target_left);
+    }
+
+
+    #[test]
+    fn first_blood_counts_only_for_the_target_s_team() {
+        let full_match: FullMatch = serde_json::from_value(serde_json::json!({
+            "players": [],
+            "objectives": [
+                { "type": "CHAT_MESSAGE_COURIER_LOST", "time": -30, "player_slot": 130 },
+                { "type": "CHAT_MESSAGE_FIRSTBLOOD", "time": 45, "player_slot": 3 },
+                { "type": "building_kill", "time": 600 },
+            ]
+        }))
+        .unwrap();
+        assert_eq!(full_match.team_first_blood(0), Some(45));
+        assert_eq!(full_match.team_first_blood(128), None);
+        assert_eq!(format_game_time(45), "0:45");
+        assert_eq!(format_game_time(-30), "-0:30");
+        assert_eq!(format_game_time(754), "12:34");
+
+        let unparsed: FullMatch = serde_json::from_value(serde_json::json!({ "players": [] }))
+            .unwrap();
+        assert_eq!(unparsed.team_first_blood(0), None);
+    }
+
+
+    #[test]
+    fn only_mapped_teammates_on_the_target_s_side_are_mentioned() {
+        let full_match: FullMatch = serde_json::from_value(serde_json::json!({
+            "players": [
+                { "account_id": 1000, "player_slot": 0 },
+                { "account_id": 11, "player_slot": 1 },
+                { "account_id": 12, "player_slot": 2 },
+                { "player_slot": 3 },
+                { "account_id": 13, "player_slot": 128 },
+            ]
+        }))
+        .unwrap();
+        let users: TeammateUsers = "11:111,13:113".parse().unwrap();
+        assert_eq!(full_match.mapped_teammates(0, &users.0), [UserId::new(111)]);
+        assert!(full_match.mapped_teammates(128, &users.0).is_empty());
+        assert!(full_match.mapped_teammates(0, &HashMap::new()).is_empty());
+    }
+
+
+    #[test]
+    fn ranked_only_keeps_ranked_matchmaking() {
+        let game = |game_mode, lobby_type| MatchData {
+            game_mode,
+            lobby_type,
+            ..MatchData::default()
+        };
+        assert!(game(22, LOBBY_TYPE_RANKED).is_ranked());
+        assert!(game(2, LOBBY_TYPE_RANKED).is_ranked());
+        // Unranked matchmaking, practice lobbies and bot matches.
+        assert!(!game(22, 0).is_ranked());
+        assert!(!game(22, 1).is_ranked());
+        assert!(!game(22, 4).is_ranked());
+        assert!(!game(GAME_MODE_TURBO, 0).is_ranked());
+    }
+
+
+    #[test]
+    fn long_unplayed_heroes_are_dusted_off() {
+        init_test_globals();
+        let day = 86400;
+        let mut state = PersistentState::default();
+        let gap = state.record_hero(2, 0);
+        assert_eq!(dusted_off(gap, 30), None);
+        let recent = state.record_hero(2, 3 * day);
+        assert_eq!(dusted_off(recent, 30), None);
+        let long = state.record_hero(2, 63 * day);
+        assert_eq!(long, Some(60));
+        assert_eq!(dusted_off(long, 30), Some(60));
+        assert_eq!(dusted_off(long, 0), None);
+
+        let dusty = MatchData { hero_id: 2, hero_gap_days: Some(60), ..MatchData::default() };
+        assert!(format_match(&dusty, "won").contains("Dusting off Axe after 60 days!"));
+        let fresh = MatchData { hero_id: 2, ..MatchData::default() };
+        assert!(!format_match(&fresh, "won").contains("Dusting off"));
+    }
+
+
+    #[test]
+    fn solo_and_party_games_pick_their_line_and_route() {
+        init_test_globals();
+        let queued = |party_size| MatchData { party_size, ..MatchData::default() };
+
+        let solo = queued(Some(1));
+        assert!(format_match(&solo, "won").contains("Solo queue."));
+        assert!(!match_announcement(&solo).party);
+
+        let party = queued(Some(3));
+        assert!(format_match(&party, "won").contains("In a party of 3."));
+        assert!(match_announcement(&party).party);
+
+        let unknown = format_match(&queued(None), "won");
+        assert!(!unknown.contains("Solo queue.") && !unknown.contains("In a party"));
+
+        assert_eq!(EventKind::Match.route().channels, [ChannelId::new(1)]);
+        assert_eq!(EventKind::Match.party_route().channels, [ChannelId::new(4)]);
+        // Only matches have a party route.
+        assert_eq!(EventKind::Presence.party_route().channels, [ChannelId::new(2)]);
+    }
+
+
+    #[test]
+    fn crossing_a_match_milestone_is_celebrated_once() {
+        init_test_globals();
+        let mut state = PersistentState { matches_played: 97, ..Default::default() };
+        let lines: Vec<String> = (0..6)
+            .filter_map(|_| state.record_match_count(&[100, 500]))
+            .filter_map(format_record)
+            .collect();
+        assert_eq!(lines, ["Match number 100 for Target!"]);
+        assert_eq!(state.matches_played, 103);
     }
 }
@@ -0,0 +1,567 @@
+use std::collections::HashMap;
+use std::sync::{ Arc, Mutex };
+
+use anyhow::{ anyhow, Result };
+use serenity::all::{
+    Channel, ChannelId, ChannelType, Colour, CreateAllowedMentions, CreateEmbed,
+    CreateEmbedAuthor, CreateForumPost, CreateMessage, EditMessage, Http, MessageId, ReactionType,
+    UserId,
+};
+use serenity::async_trait;
+use tokio::time::{ self, Duration, Instant };
+
+use crate::{
+    channel_active_remaining, clamp_message, AnnouncementRoute, EventKind, DISCORD_CONTENT_LIMIT,
+    DISCORD_EMBED_AUTHOR_LIMIT, DISCORD_EMBED_DESCRIPTION_LIMIT, DISCORD_THREAD_NAME_LIMIT,
+    FEATURES, MAX_MESSAGE_LENGTH,
+};
+
+/// The longest `active_channel_delay` holds an announcement back before
+/// posting it without TTS.
+const ACTIVE_CHANNEL_MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// A sink-agnostic announcement: what happened and how to present it.
+#[derive(Debug, Clone)]
+pub struct Announcement {
+    pub kind: EventKind,
+    pub text: String,
+    /// Extra presentation for sinks that support rich cards.
+    pub embed: EmbedData,
+    /// Names the post when it lands in a forum channel; defaults to the first line of `text`.
+    pub thread_title: Option<String>,
+    /// Users to ping alongside the route's role.
+    pub mentions: Vec<UserId>,
+    /// Sent along the `party_match` route when routes.json has one.
+    pub party: bool,
+    /// Announcements sharing a key edit the messages the first one sent
+    /// instead of posting again.
+    pub edit_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EmbedData {
+    pub title: Option<String>,
+    pub color: Option<u32>,
+}
+
+impl Announcement {
+    pub fn new(kind: EventKind, text: String) -> Self {
+        Announcement {
+            kind,
+            text,
+            embed: EmbedData::default(),
+            thread_title: None,
+            mentions: Vec::new(),
+            party: false,
+            edit_key: None,
+        }
+    }
+
+    pub fn with_embed(mut self, embed: EmbedData) -> Self {
+        self.embed = embed;
+        self
+    }
+
+    pub fn with_thread_title(mut self, title: String) -> Self {
+        self.thread_title = Some(title);
+        self
+    }
+
+    pub fn with_mentions(mut self, users: Vec<UserId>) -> Self {
+        self.mentions = users;
+        self
+    }
+
+    pub fn with_party(mut self, party: bool) -> Self {
+        self.party = party;
+        self
+    }
+
+    pub fn with_edit_key(mut self, key: String) -> Self {
+        self.edit_key = Some(key);
+        self
+    }
+
+    fn thread_title(&self) -> String {
+        let title = match &self.thread_title {
+            Some(title) => title.as_str(),
+            None => self.text.lines().next().unwrap_or_default(),
+        };
+        let title = if title.trim().is_empty() { self.kind.name() } else { title };
+        clamp_message(title, DISCORD_THREAD_NAME_LIMIT)
+    }
+}
+
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn send(&self, announcement: &Announcement) -> Result<()>;
+}
+
+/// Every write the bot makes to Discord, so message generation doesn't
+/// depend on serenity's HTTP client directly.
+#[async_trait]
+pub trait MessageSink: Send + Sync {
+    async fn send(&self, channel: ChannelId, message: CreateMessage) -> Result<MessageId>;
+    async fn edit(&self, channel: ChannelId, message: MessageId, edit: EditMessage) -> Result<()>;
+    /// Whether `channel` is a forum, where every post needs a thread of its own.
+    async fn is_forum(&self, channel: ChannelId) -> Result<bool>;
+    /// Returns the new thread and its starter message, which shares the thread's id.
+    async fn create_forum_post(
+        &self,
+        forum: ChannelId,
+        title: String,
+        message: CreateMessage,
+    ) -> Result<(ChannelId, MessageId)>;
+    async fn react(
+        &self,
+        channel: ChannelId,
+        message: MessageId,
+        reaction: ReactionType,
+    ) -> Result<()>;
+}
+
+pub struct HttpSink {
+    http: Arc<Http>,
+    /// Channel types don't change, so each channel is only looked up once.
+    forums: Mutex<HashMap<ChannelId, bool>>,
+}
+
+impl HttpSink {
+    pub fn new(http: Arc<Http>) -> Self {
+        HttpSink {
+            http,
+            forums: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl MessageSink for HttpSink {
+    async fn send(&self, channel: ChannelId, message: CreateMessage) -> Result<MessageId> {
+        Ok(channel.send_message(&self.http, message).await?.id)
+    }
+
+    async fn edit(&self, channel: ChannelId, message: MessageId, edit: EditMessage) -> Result<()> {
+        channel.edit_message(&self.http, message, edit).await?;
+        Ok(())
+    }
+
+    async fn is_forum(&self, channel: ChannelId) -> Result<bool> {
+        if let Some(is_forum) = self.forums.lock().unwrap().get(&channel) {
+            return Ok(*is_forum);
+        }
+        let is_forum = matches!(
+            channel.to_channel(&self.http).await?,
+            Channel::Guild(guild_channel) if guild_channel.kind == ChannelType::Forum
+        );
+        self.forums.lock().unwrap().insert(channel, is_forum);
+        Ok(is_forum)
+    }
+
+    async fn create_forum_post(
+        &self,
+        forum: ChannelId,
+        title: String,
+        message: CreateMessage,
+    ) -> Result<(ChannelId, MessageId)> {
+        let thread = forum
+            .create_forum_post(&self.http, CreateForumPost::new(title, message))
+            .await?;
+        Ok((thread.id, MessageId::new(thread.id.get())))
+    }
+
+    async fn react(
+        &self,
+        channel: ChannelId,
+        message: MessageId,
+        reaction: ReactionType,
+    ) -> Result<()> {
+        self.http.create_reaction(channel, message, &reaction).await?;
+        Ok(())
+    }
+}
+
+/// Posts announcements to Discord channels according to the routing table.
+pub struct DiscordNotifier {
+    sink: Arc<dyn MessageSink>,
+    /// Messages sent for each `edit_key`; a forum post is kept as its
+    /// thread's starter message.
+    posted: Mutex<HashMap<String, Vec<(ChannelId, MessageId)>>>,
+}
+
+impl DiscordNotifier {
+    pub fn new(sink: Arc<dyn MessageSink>) -> Self {
+        DiscordNotifier {
+            sink,
+            posted: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Adds the route's reaction to a sent announcement. The announcement is
+    /// out either way, so a failed reaction is only logged.
+    async fn react(&self, channel: ChannelId, message: MessageId, route: &AnnouncementRoute) {
+        let Some(reaction) = &route.reaction else {
+            return;
+        };
+        if let Err(why) = self.sink.react(channel, message, reaction.clone()).await {
+            eprintln!("Error reacting to announcement in {channel}: {why:?}");
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for DiscordNotifier {
+    async fn send(&self, announcement: &Announcement) -> Result<()> {
+        let kind = announcement.kind;
+        // Discord rejects blank messages, and a prefix or mention alone says nothing.
+        if announcement.text.trim().is_empty() {
+            eprintln!("Skipping blank {} announcement", kind.name());
+            return Ok(());
+        }
+        let mut route = if announcement.party { kind.party_route() } else { kind.route() };
+        if kind == EventKind::Presence && route.tts {
+            if FEATURES.get().unwrap().active_channel_delay {
+                let deadline = Instant::now() + ACTIVE_CHANNEL_MAX_DELAY;
+                while let Some(left) = channel_active_remaining() {
+                    let budget = deadline.saturating_duration_since(Instant::now());
+                    // A channel that never goes quiet still gets the announcement, silently.
+                    if budget.is_zero() {
+                        route.tts = false;
+                        break;
+                    }
+                    time::sleep(left.min(budget)).await;
+                }
+            } else if channel_active_remaining().is_some() {
+                route.tts = false;
+            }
+        }
+        let prefix = kind.prefix();
+        let mention = route
+            .mention
+            .map(|role| format!("<@&{role}>"))
+            .into_iter()
+            .chain(announcement.mentions.iter().map(|user| format!("<@{user}>")))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let max_length = *MAX_MESSAGE_LENGTH.get().unwrap();
+
+        // Activity texts are written by the target, so only the route's own role
+        // and the announcement's own users may ping.
+        let allowed_mentions = CreateAllowedMentions::new()
+            .roles(route.mention)
+            .users(announcement.mentions.iter().copied());
+        let (content, embed) = if route.embed {
+            let mut embed = CreateEmbed::new().description(clamp_message(
+                &announcement.text,
+                max_length.min(DISCORD_EMBED_DESCRIPTION_LIMIT),
+            ));
+            if let Some(title) = &announcement.embed.title {
+                embed = embed.title(title);
+            }
+            if let Some(color) = announcement.embed.color {
+                embed = embed.color(Colour::new(color));
+            }
+            if !prefix.is_empty() {
+                embed = embed.author(CreateEmbedAuthor::new(clamp_message(
+                    prefix,
+                    DISCORD_EMBED_AUTHOR_LIMIT,
+                )));
+            }
+            ((!mention.is_empty()).then_some(mention), Some(embed))
+        } else {
+            let content = [mention.as_str(), prefix, &announcement.text]
+                .into_iter()
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ");
+            (Some(clamp_message(&content, max_length.min(DISCORD_CONTENT_LIMIT))), None)
+        };
+
+        let posted = announcement
+            .edit_key
+            .as_ref()
+            .and_then(|key| self.posted.lock().unwrap().get(key).cloned());
+        if let Some(posted) = posted {
+            let mut edit = EditMessage::new().allowed_mentions(allowed_mentions);
+            if let Some(content) = content {
+                edit = edit.content(content);
+            }
+            if let Some(embed) = embed {
+                edit = edit.embed(embed);
+            }
+            for (channel, message) in posted {
+                if let Err(why) = self.sink.edit(channel, message, edit.clone()).await {
+                    eprintln!("Error editing {} message in {channel}: {why:?}", kind.name());
+                }
+            }
+            return Ok(());
+        }
+
+        let mut message = CreateMessage::new()
+            .tts(route.tts)
+            .allowed_mentions(allowed_mentions);
+        if let Some(content) = content {
+            message = message.content(content);
+        }
+        if let Some(embed) = embed {
+            message = message.embed(embed);
+        }
+        let mut failed = Vec::new();
+        let mut sent_ids = Vec::new();
+        for channel in &route.channels {
+            let sent = match self.sink.is_forum(*channel).await {
+                Ok(true) => {
+                    let title = announcement.thread_title();
+                    self.sink.create_forum_post(*channel, title, message.clone()).await
+                }
+                Ok(false) => {
+                    self.sink.send(*channel, message.clone()).await.map(|id| (*channel, id))
+                }
+                Err(err) => Err(err),
+            };
+            match sent {
+                Ok((posted_in, id)) => {
+                    self.react(posted_in, id, &route).await;
+                    sent_ids.push((posted_in, id));
+                }
+                Err(why) => {
+                    eprintln!(
+                        "Error sending {} message to {channel}: {why:?}",
+                        kind.name()
+                    );
+                    failed.push(channel.to_string());
+                }
+            }
+        }
+        if let Some(key) = &announcement.edit_key {
+            self.posted.lock().unwrap().insert(key.clone(), sent_ids);
+        }
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("couldn't post to channels {}", failed.join(", ")))
+        }
+    }
+}
+
+/// Keeps every announcement it is handed, for tests that don't care how it
+/// would look on Discord.
+#[cfg(test)]
+pub struct RecordingNotifier {
+    pub sent: &'static Mutex<Vec<Announcement>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl Notifier for RecordingNotifier {
+    async fn send(&self, announcement: &Announcement) -> Result<()> {
+        self.sent.lock().unwrap().push(announcement.clone());
+        Ok(())
+    }
+}
+
+/// Everything a `RecordingSink` was asked to do, with messages as the JSON
+/// Discord would receive.
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum SinkCall {
+    Send { channel: ChannelId, message: serde_json::Value },
+    Edit { channel: ChannelId, message: MessageId, edit: serde_json::Value },
+    ForumPost { forum: ChannelId, title: String, message: serde_json::Value },
+    React { channel: ChannelId, message: MessageId, reaction: ReactionType },
+}
+
+/// Records calls instead of talking to Discord. Channels in `forums` count as
+/// forums; every sent message and forum thread gets the next id.
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingSink {
+    pub forums: Vec<ChannelId>,
+    pub calls: Mutex<Vec<SinkCall>>,
+    /// Errors the next reactions fail with, in order. Reactions succeed once
+    /// it runs out.
+    pub reaction_errors: Mutex<Vec<anyhow::Error>>,
+}
+
+#[cfg(test)]
+impl RecordingSink {
+    pub fn calls(&self) -> Vec<SinkCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn record(&self, call: SinkCall) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+#[cfg(test)]
+#[async_trait]
+impl MessageSink for RecordingSink {
+    async fn send(&self, channel: ChannelId, message: CreateMessage) -> Result<MessageId> {
+        let message = serde_json::to_value(message)?;
+        let mut calls = self.calls.lock().unwrap();
+        calls.push(SinkCall::Send { channel, message });
+        Ok(MessageId::new(calls.len() as u64))
+    }
+
+    async fn edit(&self, channel: ChannelId, message: MessageId, edit: EditMessage) -> Result<()> {
+        let edit = serde_json::to_value(edit)?;
+        self.record(SinkCall::Edit { channel, message, edit });
+        Ok(())
+    }
+
+    async fn is_forum(&self, channel: ChannelId) -> Result<bool> {
+        Ok(self.forums.contains(&channel))
+    }
+
+    async fn create_forum_post(
+        &self,
+        forum: ChannelId,
+        title: String,
+        message: CreateMessage,
+    ) -> Result<(ChannelId, MessageId)> {
+        let message = serde_json::to_value(message)?;
+        let mut calls = self.calls.lock().unwrap();
+        calls.push(SinkCall::ForumPost { forum, title, message });
+        let thread = calls.len() as u64;
+        Ok((ChannelId::new(thread), MessageId::new(thread)))
+    }
+
+    async fn react(
+        &self,
+        channel: ChannelId,
+        message: MessageId,
+        reaction: ReactionType,
+    ) -> Result<()> {
+        self.record(SinkCall::React { channel, message, reaction });
+        let mut errors = self.reaction_errors.lock().unwrap();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.remove(0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::init_test_globals;
+
+    async fn send(sink: &Arc<RecordingSink>, announcement: Announcement) -> Vec<SinkCall> {
+        init_test_globals();
+        DiscordNotifier::new(sink.clone()).send(&announcement).await.unwrap();
+        sink.calls()
+    }
+
+    #[tokio::test]
+    async fn plain_route_prefixes_content_and_pings_only_announced_users() {
+        let sink = Arc::new(RecordingSink::default());
+        let announcement = Announcement::new(EventKind::Presence, "plays @everyone".to_string())
+            .with_mentions(vec![UserId::new(7)]);
+        let calls = send(&sink, announcement).await;
+
+        let [SinkCall::Send { channel, message }] = calls.as_slice() else {
+            panic!("expected one send, got {calls:?}");
+        };
+        assert_eq!(*channel, ChannelId::new(2));
+        assert_eq!(message["content"], "<@7> [activity] plays @everyone");
+        assert_eq!(message["tts"], false);
+        assert_eq!(
+            message["allowed_mentions"],
+            json!({ "parse": [], "users": ["7"], "roles": [] })
+        );
+    }
+
+    #[tokio::test]
+    async fn embed_route_puts_text_in_the_embed_and_reacts() {
+        let sink = Arc::new(RecordingSink::default());
+        let announcement = Announcement::new(EventKind::Match, "Won".to_string())
+            .with_embed(EmbedData { title: Some("Axe".to_string()), color: Some(0xff0000) });
+        let calls = send(&sink, announcement).await;
+
+        let [SinkCall::Send { channel, message }, SinkCall::React { reaction, .. }] =
+            calls.as_slice()
+        else {
+            panic!("expected a send and a reaction, got {calls:?}");
+        };
+        assert_eq!(*channel, ChannelId::new(1));
+        assert_eq!(message["content"], "<@&5>");
+        assert_eq!(message["embeds"][0]["description"], "Won");
+        assert_eq!(message["embeds"][0]["title"], "Axe");
+        assert_eq!(message["embeds"][0]["color"], 0xff0000);
+        assert_eq!(message["allowed_mentions"]["roles"], json!(["5"]));
+        assert_eq!(*reaction, ReactionType::Unicode("🔥".to_string()));
+    }
+
+    #[tokio::test]
+    async fn forum_channels_get_a_post_titled_by_the_first_line() {
+        let sink = Arc::new(RecordingSink {
+            forums: vec![ChannelId::new(3)],
+            ..RecordingSink::default()
+        });
+        let announcement = Announcement::new(EventKind::Lifecycle, "Started\nv2".to_string());
+        let calls = send(&sink, announcement).await;
+
+        let [SinkCall::ForumPost { forum, title, message }] = calls.as_slice() else {
+            panic!("expected one forum post, got {calls:?}");
+        };
+        assert_eq!(*forum, ChannelId::new(3));
+        assert_eq!(title, "Started");
+        assert_eq!(message["content"], "Started\nv2");
+    }
+
+    #[tokio::test]
+    async fn blank_announcements_are_skipped() {
+        let sink = Arc::new(RecordingSink::default());
+        let calls = send(&sink, Announcement::new(EventKind::Presence, " \n".to_string())).await;
+        assert!(calls.is_empty());
+    }
+
+
+    #[tokio::test]
+    async fn announcements_sharing_an_edit_key_edit_the_first_post() {
+        init_test_globals();
+        let sink = Arc::new(RecordingSink::default());
+        let notifier = DiscordNotifier::new(sink.clone());
+        let stream = |title: &str| {
+            Announcement::new(EventKind::Presence, format!("is streaming: {title}"))
+                .with_edit_key("stream-0".to_string())
+        };
+        notifier.send(&stream("Ranked")).await.unwrap();
+        notifier.send(&stream("Turbo")).await.unwrap();
+
+        let calls = sink.calls();
+        let [SinkCall::Send { channel, .. }, SinkCall::Edit { channel: edited, message, edit }] =
+            calls.as_slice()
+        else {
+            panic!("expected a send and an edit, got {calls:?}");
+        };
+        assert_eq!(*channel, ChannelId::new(2));
+        assert_eq!((*edited, *message), (ChannelId::new(2), MessageId::new(1)));
+        assert_eq!(edit["content"], "[activity] is streaming: Turbo");
+    }
+
+
+    #[tokio::test]
+    async fn forum_posts_get_the_route_reaction_on_their_starter_message() {
+        let sink = Arc::new(RecordingSink {
+            forums: vec![ChannelId::new(1)],
+            ..RecordingSink::default()
+        });
+        let calls = send(&sink, Announcement::new(EventKind::Match, "Won".to_string())).await;
+
+        let [SinkCall::ForumPost { forum, .. }, SinkCall::React { channel, message, reaction }] =
+            calls.as_slice()
+        else {
+            panic!("expected a forum post and a reaction, got {calls:?}");
+        };
+        assert_eq!(*forum, ChannelId::new(1));
+        // The thread is the first call's id, and its starter message shares it.
+        assert_eq!((*channel, *message), (ChannelId::new(1), MessageId::new(1)));
+        assert_eq!(*reaction, ReactionType::Unicode("🔥".to_string()));
+    }
+}
@@ -0,0 +1,81 @@
+use tokio::sync::Mutex;
+use tokio::time::{ self, Duration, Instant };
+
+/// Token bucket shared by every request to one API, so independent pollers
+/// together stay within the API's per-minute budget.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_minute: u32,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        assert!(per_minute > 0, "rate limit must allow at least one request per minute");
+        RateLimiter {
+            per_minute,
+            bucket: Mutex::new(Bucket {
+                tokens: per_minute as f64,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Waits until a request may be issued. Callers are served in order
+    /// because the bucket stays locked while waiting for a token.
+    pub async fn acquire(&self) {
+        let mut bucket = self.bucket.lock().await;
+        while let Err(wait) = bucket.take(Instant::now(), self.per_minute) {
+            time::sleep(wait).await;
+        }
+    }
+}
+
+impl Bucket {
+    /// Refills the bucket up to `now` and takes a token, or returns how long
+    /// until the next one.
+    fn take(&mut self, now: Instant, per_minute: u32) -> Result<(), Duration> {
+        let per_second = per_minute as f64 / 60.0;
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_second).min(per_minute as f64);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64((1.0 - self.tokens) / per_second))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_runs_dry_and_refills_over_time() {
+        let start = Instant::now();
+        let mut bucket = Bucket { tokens: 60.0, refilled_at: start };
+        for _ in 0..60 {
+            assert_eq!(bucket.take(start, 60), Ok(()));
+        }
+        assert_eq!(bucket.take(start, 60), Err(Duration::from_secs(1)));
+
+        let later = start + Duration::from_millis(2500);
+        assert_eq!(bucket.take(later, 60), Ok(()));
+        assert_eq!(bucket.take(later, 60), Ok(()));
+        assert_eq!(bucket.take(later, 60), Err(Duration::from_millis(500)));
+        // A long pause only refills up to the per-minute budget.
+        let much_later = later + Duration::from_secs(3600);
+        for _ in 0..60 {
+            assert_eq!(bucket.take(much_later, 60), Ok(()));
+        }
+        assert!(bucket.take(much_later, 60).is_err());
+    }
+}
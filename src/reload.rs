@@ -0,0 +1,111 @@
+use std::sync::RwLock;
+
+use anyhow::Result;
+
+use crate::features::Features;
+use crate::{
+    load_features, load_localization, load_routes, Localization, RoutingTable, FEATURES,
+    LOCALIZATION, ROUTES,
+};
+
+/// A global that's set once at startup and may be swapped later by `/reload`.
+/// Replaced values are leaked so `get` can keep handing out `'static`
+/// references; reloads are rare admin actions, so the leak stays small.
+pub struct Reloadable<T: 'static> {
+    current: RwLock<Option<&'static T>>,
+}
+
+impl<T> Reloadable<T> {
+    pub const fn new() -> Self {
+        Reloadable {
+            current: RwLock::new(None),
+        }
+    }
+
+    pub fn get(&self) -> Option<&'static T> {
+        *self.current.read().unwrap()
+    }
+
+    /// Sets the initial value, handing `value` back if one is already set.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut current = self.current.write().unwrap();
+        if current.is_some() {
+            return Err(value);
+        }
+        *current = Some(Box::leak(Box::new(value)));
+        Ok(())
+    }
+
+    /// Swaps in `value`, returning whether it differs from the previous one.
+    pub fn replace(&self, value: T) -> bool
+    where
+        T: PartialEq,
+    {
+        let mut current = self.current.write().unwrap();
+        if current.is_some_and(|old| *old == value) {
+            return false;
+        }
+        *current = Some(Box::leak(Box::new(value)));
+        true
+    }
+}
+
+/// Re-reads `localization.json`, `features.json` and `routes.json`. Nothing is
+/// swapped unless all of them are valid. Returns the names of changed files.
+pub fn reload() -> Result<Vec<&'static str>> {
+    reload_with(load_localization, load_features, load_routes)
+}
+
+fn reload_with(
+    load_localization: impl FnOnce() -> Result<Localization>,
+    load_features: impl FnOnce() -> Result<Features>,
+    load_routes: impl FnOnce() -> Result<RoutingTable>,
+) -> Result<Vec<&'static str>> {
+    let locals = load_localization()?;
+    let features = load_features()?;
+    let routes = load_routes()?;
+
+    let mut changed = Vec::new();
+    if LOCALIZATION.replace(locals) {
+        changed.push("localization.json");
+    }
+    if FEATURES.replace(features) {
+        changed.push("features.json");
+    }
+    if ROUTES.replace(routes) {
+        changed.push("routes.json");
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+    use crate::{ init_test_globals, test_localization };
+
+    #[test]
+    fn replace_reports_only_real_changes() {
+        static VALUE: Reloadable<u32> = Reloadable::new();
+        assert_eq!(VALUE.set(1), Ok(()));
+        assert_eq!(VALUE.set(2), Err(2));
+        assert!(!VALUE.replace(1));
+        assert!(VALUE.replace(3));
+        assert_eq!(VALUE.get(), Some(&3));
+    }
+
+    #[test]
+    fn invalid_files_keep_the_current_config() {
+        init_test_globals();
+        let renamed = || {
+            Ok(Localization {
+                target_name: "Renamed".to_string(),
+                ..test_localization()
+            })
+        };
+        let err = reload_with(renamed, || Err(anyhow!("Invalid features")), || unreachable!());
+        assert_eq!(err.unwrap_err().to_string(), "Invalid features");
+        assert_eq!(LOCALIZATION.get().unwrap().target_name, "Target");
+    }
+}
@@ -0,0 +1,239 @@
+use std::sync::Mutex;
+
+use tokio::time::{ self, Duration, Instant };
+
+use crate::notifier::Announcement;
+use crate::{
+    announce, dota_presence, render_template, target_name, EventKind, DOTA_IDLE_MINUTES,
+    LOCALIZATION, LONG_SESSION_HOURS,
+};
+
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+static CURRENT_SESSION: Mutex<Option<GameSession>> = Mutex::new(None);
+
+/// The game the target is currently playing according to presence.
+#[derive(Debug)]
+struct GameSession {
+    game: String,
+    started: Instant,
+    /// Milestones (in hours) already announced for this session.
+    announced: Vec<u64>,
+    /// Session start or the last completed match, whichever is later.
+    idle_since: Instant,
+    /// Whether the idle note was already sent since `idle_since`.
+    idle_noted: bool,
+    /// Matches completed since the session started.
+    matches: u32,
+}
+
+/// What a session amounted to once the target stopped playing.
+#[derive(Debug)]
+pub struct SessionStats {
+    pub game: String,
+    pub played: Duration,
+    pub matches: u32,
+}
+
+/// Starts a new session when the played game changes and ends it when the
+/// target stops playing. Repeated updates for the same game keep the session.
+/// Returns the stats of a session that ended because nothing is played anymore.
+pub fn track_game(game: Option<&str>) -> Option<SessionStats> {
+    track(&mut CURRENT_SESSION.lock().unwrap(), game, Instant::now())
+}
+
+fn track(
+    session: &mut Option<GameSession>,
+    game: Option<&str>,
+    now: Instant,
+) -> Option<SessionStats> {
+    match game {
+        Some(game) if session.as_ref().is_some_and(|s| s.game == game) => {}
+        Some(game) => {
+            *session = Some(GameSession {
+                game: game.to_string(),
+                started: now,
+                announced: Vec::new(),
+                idle_since: now,
+                idle_noted: false,
+                matches: 0,
+            })
+        }
+        None => {
+            return session.take().map(|ended| SessionStats {
+                game: ended.game,
+                played: now.duration_since(ended.started),
+                matches: ended.matches,
+            })
+        }
+    }
+    None
+}
+
+impl SessionStats {
+    /// Fills `template`'s `{game}`, `{minutes}` and `{matches}` placeholders.
+    pub fn render(&self, template: &str) -> String {
+        render_template(
+            template,
+            &[
+                ("game", &self.game),
+                ("minutes", &(self.played.as_secs() / 60).to_string()),
+                ("matches", &self.matches.to_string()),
+            ],
+        )
+    }
+}
+
+/// The game being played right now, if any.
+pub fn current_game() -> Option<String> {
+    CURRENT_SESSION.lock().unwrap().as_ref().map(|session| session.game.clone())
+}
+
+/// Restarts the idle timer of the current session after a completed match.
+pub fn record_match_finished() {
+    if let Some(session) = CURRENT_SESSION.lock().unwrap().as_mut() {
+        session.record_match(Instant::now());
+    }
+}
+
+/// Returns the minutes spent in Dota without finishing a match once they
+/// exceed `threshold`, at most once per gap between matches.
+fn due_idle_note(threshold: Duration) -> Option<u64> {
+    let mut session = CURRENT_SESSION.lock().unwrap();
+    let session = session.as_mut()?;
+    if session.game != dota_presence::DOTA_ACTIVITY_NAME || session.idle_noted {
+        return None;
+    }
+    let idle = session.idle_since.elapsed();
+    if idle < threshold {
+        return None;
+    }
+    session.idle_noted = true;
+    Some(idle.as_secs() / 60)
+}
+
+/// Returns the game and the highest newly reached milestone, marking every
+/// reached milestone as announced so each fires once per session.
+fn due_milestone(thresholds: &[u64]) -> Option<(String, u64)> {
+    let mut session = CURRENT_SESSION.lock().unwrap();
+    let session = session.as_mut()?;
+    let hours = session.due_milestone(thresholds, Instant::now())?;
+    Some((session.game.clone(), hours))
+}
+
+impl GameSession {
+    fn record_match(&mut self, now: Instant) {
+        self.idle_since = now;
+        self.idle_noted = false;
+        self.matches += 1;
+    }
+
+    fn due_milestone(&mut self, thresholds: &[u64], now: Instant) -> Option<u64> {
+        let hours_played = now.duration_since(self.started).as_secs() / 3600;
+        let due: Vec<u64> = thresholds
+            .iter()
+            .copied()
+            .filter(|hours| *hours <= hours_played && !self.announced.contains(hours))
+            .collect();
+        let highest = due.iter().copied().max()?;
+        self.announced.extend(due);
+        Some(highest)
+    }
+}
+
+pub async fn session_loop() {
+    let thresholds = LONG_SESSION_HOURS.get().unwrap();
+    let idle_minutes = *DOTA_IDLE_MINUTES.get().unwrap();
+    if thresholds.is_empty() && idle_minutes == 0 {
+        return;
+    }
+
+    let mut interval = time::interval(SESSION_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        // Looked up every tick so `/reload` can change the templates.
+        let locals = LOCALIZATION.get().unwrap();
+        let still_playing = locals.still_playing.as_deref().filter(|_| !thresholds.is_empty());
+        let no_match_yet = locals.no_match_yet.as_deref().filter(|_| idle_minutes > 0);
+        if let Some(template) = still_playing {
+            if let Some((game, hours)) = due_milestone(thresholds) {
+                let content = render_template(
+                    template,
+                    &[
+                        ("target_name", &target_name()),
+                        ("game", &game),
+                        ("hours", &hours.to_string()),
+                    ],
+                );
+                announce(Announcement::new(EventKind::Presence, content)).await;
+            }
+        }
+        if let Some(template) = no_match_yet {
+            if let Some(minutes) = due_idle_note(Duration::from_secs(idle_minutes * 60)) {
+                let content = render_template(
+                    template,
+                    &[
+                        ("target_name", &target_name()),
+                        ("minutes", &minutes.to_string()),
+                    ],
+                );
+                announce(Announcement::new(EventKind::Presence, content)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(game: &str, started: Instant) -> GameSession {
+        GameSession {
+            game: game.to_string(),
+            started,
+            announced: Vec::new(),
+            idle_since: started,
+            idle_noted: false,
+            matches: 0,
+        }
+    }
+
+    #[test]
+    fn each_milestone_is_announced_once_per_session() {
+        let started = Instant::now();
+        let after = |hours: u64| started + Duration::from_secs(hours * 3600);
+        let mut session = session("Terraria", started);
+        assert_eq!(session.due_milestone(&[3, 6], after(2)), None);
+        assert_eq!(session.due_milestone(&[3, 6], after(3)), Some(3));
+        assert_eq!(session.due_milestone(&[3, 6], after(4)), None);
+        // Waking up after both only announces the highest.
+        let mut session = self::session("Terraria", started);
+        assert_eq!(session.due_milestone(&[3, 6], after(7)), Some(6));
+        assert_eq!(session.due_milestone(&[3, 6], after(8)), None);
+    }
+
+    #[test]
+    fn finished_sessions_report_their_length_and_matches() {
+        let started = Instant::now();
+        let after = |minutes: u64| started + Duration::from_secs(minutes * 60);
+        let mut current = None;
+        assert!(track(&mut current, Some("Dota 2"), started).is_none());
+        // Further updates for the same game keep the session going.
+        assert!(track(&mut current, Some("Dota 2"), after(10)).is_none());
+        current.as_mut().unwrap().record_match(after(40));
+        current.as_mut().unwrap().record_match(after(80));
+
+        let stats = track(&mut current, None, after(95)).unwrap();
+        assert_eq!(
+            stats.render("Played {game} for {minutes} minutes, {matches} matches"),
+            "Played Dota 2 for 95 minutes, 2 matches"
+        );
+        assert!(current.is_none());
+
+        // The next session counts from zero again.
+        track(&mut current, Some("Dota 2"), after(120));
+        let stats = track(&mut current, None, after(130)).unwrap();
+        assert_eq!((stats.played, stats.matches), (Duration::from_secs(600), 0));
+        assert!(track(&mut current, None, after(140)).is_none());
+    }
+}
@@ -0,0 +1,225 @@
+use std::str::FromStr;
+
+use anyhow::{ anyhow, Result };
+use serde::Deserialize;
+use serenity::async_trait;
+
+use crate::error::FetchError;
+use crate::steam::steam_get_json;
+use crate::{ opendota_get, opendota_url, MatchData, Response };
+
+const VALVE_API_BASE_URL: &str = "https://api.steampowered.com/IDOTA2Match_570";
+
+/// Where the latest matches come from, picked with `MATCH_SOURCE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchSource {
+    OpenDota,
+    Valve,
+}
+
+impl FromStr for MatchSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "opendota" => Ok(MatchSource::OpenDota),
+            "valve" => Ok(MatchSource::Valve),
+            _ => Err(anyhow!("expected opendota or valve, got {s}")),
+        }
+    }
+}
+
+/// Fetches the target's recent matches, newest first.
+#[async_trait]
+pub trait DotaSource: Send + Sync {
+    async fn recent_matches(&self) -> Result<Vec<MatchData>>;
+}
+
+pub struct OpenDotaSource {
+    url: String,
+}
+
+impl OpenDotaSource {
+    pub fn new(account_id: u64) -> Self {
+        OpenDotaSource {
+            url: opendota_url(&format!("players/{account_id}/recentMatches")),
+        }
+    }
+}
+
+#[async_trait]
+impl DotaSource for OpenDotaSource {
+    async fn recent_matches(&self) -> Result<Vec<MatchData>> {
+        let body = opendota_get(&self.url).await?;
+        let response: Response<MatchData> = serde_json::from_str(&body)?;
+        Ok(response.items)
+    }
+}
+
+/// The official Dota 2 Web API. Only the latest match is returned, since
+/// every match needs its own details request.
+/// Uses the shared `STEAM_API_KEY` keys.
+pub struct ValveSource {
+    account_id: u64,
+}
+
+impl ValveSource {
+    pub fn new(account_id: u64) -> Self {
+        ValveSource { account_id }
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(
+        &self,
+        method: &str,
+        query: &str,
+    ) -> Result<T, FetchError> {
+        let url = format!("{VALVE_API_BASE_URL}/{method}/V001/?{query}");
+        let body: ValveResponse<T> = steam_get_json(&url).await?;
+        Ok(body.result)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ValveResponse<T> {
+    result: T,
+}
+
+#[derive(Debug, Deserialize)]
+struct ValveMatchHistory {
+    #[serde(default)]
+    matches: Vec<ValveMatchSummary>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ValveMatchSummary {
+    match_id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ValveMatchDetails {
+    match_id: i64,
+    radiant_win: bool,
+    duration: i64,
+    start_time: i64,
+    #[serde(default)]
+    game_mode: i64,
+    #[serde(default)]
+    lobby_type: i64,
+    players: Vec<ValvePlayer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ValvePlayer {
+    #[serde(default)]
+    account_id: Option<u64>,
+    player_slot: i64,
+    hero_id: i64,
+    #[serde(default)]
+    kills: Option<i64>,
+    #[serde(default)]
+    deaths: Option<i64>,
+    #[serde(default)]
+    assists: Option<i64>,
+    #[serde(default)]
+    gold_per_min: Option<i64>,
+}
+
+impl ValveMatchDetails {
+    /// The match as seen by `account_id`, if they played in it.
+    fn into_match_data(self, account_id: u64) -> Option<MatchData> {
+        let player = self
+            .players
+            .into_iter()
+            .find(|player| player.account_id == Some(account_id))?;
+        Some(MatchData {
+            match_id: self.match_id,
+            player_slot: player.player_slot,
+            hero_id: player.hero_id,
+            radiant_win: Some(self.radiant_win),
+            duration: Some(self.duration),
+            kills: player.kills,
+            deaths: player.deaths,
+            assists: player.assists,
+            game_mode: self.game_mode,
+            lobby_type: self.lobby_type,
+            start_time: Some(self.start_time),
+            gold_per_min: player.gold_per_min,
+            ..MatchData::default()
+        })
+    }
+}
+
+#[async_trait]
+impl DotaSource for ValveSource {
+    async fn recent_matches(&self) -> Result<Vec<MatchData>> {
+        let history: ValveMatchHistory = self
+            .get(
+                "GetMatchHistory",
+                &format!("account_id={}&matches_requested=1", self.account_id),
+            )
+            .await?;
+        let Some(latest) = history.matches.first() else {
+            return Ok(Vec::new());
+        };
+        let details: ValveMatchDetails = self
+            .get("GetMatchDetails", &format!("match_id={}", latest.match_id))
+            .await?;
+        Ok(details.into_match_data(self.account_id).into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_sources_parse_from_config() {
+        assert_eq!("opendota".parse::<MatchSource>().unwrap(), MatchSource::OpenDota);
+        assert_eq!("valve".parse::<MatchSource>().unwrap(), MatchSource::Valve);
+        assert!("stratz".parse::<MatchSource>().is_err());
+    }
+
+    #[test]
+    fn valve_match_details_become_the_target_s_match() {
+        let history: ValveResponse<ValveMatchHistory> = serde_json::from_str(
+            r#"{ "result": { "status": 1, "matches": [
+                { "match_id": 7001 }, { "match_id": 7000 }
+            ] } }"#,
+        )
+        .unwrap();
+        assert_eq!(history.result.matches[0].match_id, 7001);
+
+        let details: ValveResponse<ValveMatchDetails> = serde_json::from_str(
+            r#"{ "result": {
+                "match_id": 7001, "radiant_win": false, "duration": 2400,
+                "start_time": 1700000000, "game_mode": 22, "lobby_type": 7,
+                "players": [
+                    { "account_id": 4294967295, "player_slot": 0, "hero_id": 1 },
+                    { "account_id": 1000, "player_slot": 130, "hero_id": 2,
+                      "kills": 9, "deaths": 1, "assists": 12, "gold_per_min": 640 }
+                ]
+            } }"#,
+        )
+        .unwrap();
+        let last = details.result.into_match_data(1000).unwrap();
+        assert_eq!(last.match_id, 7001);
+        assert_eq!(last.player_slot, 130);
+        assert_eq!(last.hero_id, 2);
+        assert!(last.is_win());
+        assert_eq!((last.kills, last.deaths, last.assists), (Some(9), Some(1), Some(12)));
+        assert_eq!(last.duration, Some(2400));
+        assert_eq!(last.start_time, Some(1700000000));
+        assert_eq!(last.lobby_type, 7);
+        assert_eq!(last.gold_per_min, Some(640));
+    }
+
+    #[test]
+    fn valve_matches_without_the_target_are_skipped() {
+        let details: ValveMatchDetails = serde_json::from_str(
+            r#"{ "match_id": 1, "radiant_win": true, "duration": 60, "start_time": 0,
+                 "players": [{ "player_slot": 0, "hero_id": 1 }] }"#,
+        )
+        .unwrap();
+        assert!(details.into_match_data(1000).is_none());
+    }
+}
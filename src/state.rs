@@ -0,0 +1,466 @@
+use std::collections::{ BTreeMap, BTreeSet, VecDeque };
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::{ Deserialize, Serialize };
+use serde_json::Value;
+
+/// Bumped whenever the persisted layout changes; older files are upgraded by `migrate`.
+pub const STATE_VERSION: u64 = 8;
+
+/// Everything the bot remembers between restarts, kept in a single file so
+/// the pieces can never disagree with each other.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PersistentState {
+    pub version: u64,
+    pub last_match_id: i64,
+    /// Consecutive wins when positive, consecutive losses when negative.
+    pub streak: i64,
+    pub last_win: Option<bool>,
+    /// Longest win streak ever seen.
+    pub best_streak: i64,
+    /// Match with the best KDA ratio ever seen.
+    pub best_kda: Option<KdaRecord>,
+    /// Unix start time of the last recorded match.
+    pub last_match_start: Option<i64>,
+    /// Position on the Immortal leaderboard, if the target is on it.
+    pub leaderboard_rank: Option<i64>,
+    /// Unix start time of the last match on each hero, by hero id.
+    pub hero_last_played: BTreeMap<i64, i64>,
+    /// Matches recorded since the bot started tracking the target.
+    pub matches_played: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct KdaRecord {
+    pub match_id: i64,
+    pub kills: i64,
+    pub deaths: i64,
+    pub assists: i64,
+}
+
+impl KdaRecord {
+    /// (kills + assists) / deaths, counting deathless games as one death.
+    pub fn ratio(&self) -> f64 {
+        (self.kills + self.assists) as f64 / self.deaths.max(1) as f64
+    }
+}
+
+/// How the target's leaderboard position moved since it was last checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeaderboardChange {
+    Entered(i64),
+    Moved { from: i64, to: i64 },
+    Left(i64),
+}
+
+/// A personal best beaten by the latest match.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NewRecord {
+    Streak(i64),
+    Kda(KdaRecord),
+    /// The match count reached one of the configured milestones.
+    Milestone(u64),
+}
+
+impl Default for PersistentState {
+    fn default() -> Self {
+        PersistentState {
+            version: STATE_VERSION,
+            last_match_id: 0,
+            streak: 0,
+            last_win: None,
+            best_streak: 0,
+            best_kda: None,
+            last_match_start: None,
+            leaderboard_rank: None,
+            hero_last_played: BTreeMap::new(),
+            matches_played: 0,
+        }
+    }
+}
+
+impl PersistentState {
+    /// Updates the streak, returning a new record when it beats the previous
+    /// best. The very first record is only stored, there's nothing to beat yet.
+    pub fn record_result(&mut self, win: bool) -> Option<NewRecord> {
+        self.streak = if win {
+            self.streak.max(0) + 1
+        } else {
+            self.streak.min(0) - 1
+        };
+        self.last_win = Some(win);
+        if self.streak <= self.best_streak {
+            return None;
+        }
+        let beaten = self.best_streak > 0;
+        self.best_streak = self.streak;
+        beaten.then_some(NewRecord::Streak(self.streak))
+    }
+
+    /// Like `record_result`, for the best KDA.
+    pub fn record_kda(&mut self, kda: KdaRecord) -> Option<NewRecord> {
+        match self.best_kda {
+            Some(best) if kda.ratio() <= best.ratio() => None,
+            Some(_) => {
+                self.best_kda = Some(kda);
+                Some(NewRecord::Kda(kda))
+            }
+            None => {
+                self.best_kda = Some(kda);
+                None
+            }
+        }
+    }
+
+    /// Counts a match, returning the new count when it is one of `milestones`.
+    /// The count only grows, so each milestone is reached once.
+    pub fn record_match_count(&mut self, milestones: &[u64]) -> Option<NewRecord> {
+        self.matches_played += 1;
+        milestones
+            .contains(&self.matches_played)
+            .then_some(NewRecord::Milestone(self.matches_played))
+    }
+
+    /// Records a match on `hero_id` starting at `start_time`, returning how
+    /// many whole days it had been since the hero was last played.
+    pub fn record_hero(&mut self, hero_id: i64, start_time: i64) -> Option<i64> {
+        let previous = self.hero_last_played.insert(hero_id, start_time)?;
+        Some((start_time - previous).max(0) / 86400)
+    }
+
+    /// Stores the latest leaderboard rank, returning how it changed.
+    pub fn record_leaderboard_rank(&mut self, rank: Option<i64>) -> Option<LeaderboardChange> {
+        let previous = std::mem::replace(&mut self.leaderboard_rank, rank);
+        match (previous, rank) {
+            (None, Some(rank)) => Some(LeaderboardChange::Entered(rank)),
+            (Some(from), Some(to)) if from != to => Some(LeaderboardChange::Moved { from, to }),
+            (Some(rank), None) => Some(LeaderboardChange::Left(rank)),
+            _ => None,
+        }
+    }
+
+    /// Reads the state from `path`, starting fresh if the file is missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        let body = match fs::read_to_string(path) {
+            Ok(body) => body,
+            Err(_) => return Self::default(),
+        };
+        let parsed = serde_json::from_str(&body)
+            .map_err(anyhow::Error::from)
+            .and_then(migrate)
+            .and_then(|value| Ok(serde_json::from_value(value)?));
+        parsed.unwrap_or_else(|err| {
+            eprintln!("Invalid state file {}, starting fresh: {err}", path.display());
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomically(path, self)
+    }
+}
+
+/// Recently announced match ids, oldest first. Kept in its own file so a lost
+/// or reset state file can't make the bot announce an old match again.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SeenMatches {
+    ids: VecDeque<i64>,
+}
+
+impl SeenMatches {
+    /// Records `match_id`, keeping at most `capacity` ids. Returns false if
+    /// it was already seen.
+    pub fn insert(&mut self, match_id: i64, capacity: usize) -> bool {
+        if self.ids.contains(&match_id) {
+            return false;
+        }
+        // A loop rather than a single pop, in case the cap was lowered since the last run.
+        while self.ids.len() >= capacity {
+            self.ids.pop_front();
+        }
+        self.ids.push_back(match_id);
+        true
+    }
+
+    /// Reads the ids from `path`, starting empty if the file is missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        load_or_default(path, "seen matches")
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomically(path, self)
+    }
+}
+
+/// App ids of the games the target owns on Steam. Empty until the library
+/// has been checked once, so the games owned before aren't announced.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct OwnedGames {
+    app_ids: Option<BTreeSet<u32>>,
+}
+
+impl OwnedGames {
+    /// Stores `owned` as the full library and returns the app ids that
+    /// weren't in it before. The first check returns nothing.
+    pub fn update(&mut self, owned: BTreeSet<u32>) -> Vec<u32> {
+        let new = match &self.app_ids {
+            Some(known) => owned.difference(known).copied().collect(),
+            None => Vec::new(),
+        };
+        self.app_ids = Some(owned);
+        new
+    }
+
+    /// Reads the app ids from `path`, starting unchecked if the file is missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        load_or_default(path, "owned games")
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomically(path, self)
+    }
+}
+
+/// Steam achievements already unlocked, by app id. Apps only show up once
+/// they have been checked, so their existing unlocks aren't announced.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct UnlockedAchievements {
+    apps: BTreeMap<u32, BTreeSet<String>>,
+}
+
+impl UnlockedAchievements {
+    /// Stores `unlocked` as the full set for `app_id` and returns the ids that
+    /// weren't known before. The first check of an app returns nothing.
+    pub fn update(&mut self, app_id: u32, unlocked: BTreeSet<String>) -> Vec<String> {
+        let new = match self.apps.get(&app_id) {
+            Some(known) => unlocked.difference(known).cloned().collect(),
+            None => Vec::new(),
+        };
+        self.apps.insert(app_id, unlocked);
+        new
+    }
+
+    /// Reads the sets from `path`, starting empty if the file is missing or unreadable.
+    pub fn load(path: &Path) -> Self {
+        load_or_default(path, "achievements")
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomically(path, self)
+    }
+}
+
+/// Writes `value` to a temporary file and renames it over `path`, so a
+/// crash mid-write never leaves a truncated file behind.
+fn write_atomically(path: &Path, value: &impl Serialize) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_string_pretty(value)?)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Reads `path` as JSON, starting fresh with the default when the file is
+/// missing or invalid. `what` names the file in the warning.
+fn load_or_default<T: DeserializeOwned + Default>(path: &Path, what: &str) -> T {
+    let body = match fs::read_to_string(path) {
+        Ok(body) => body,
+        Err(_) => return T::default(),
+    };
+    serde_json::from_str(&body).unwrap_or_else(|err| {
+        eprintln!("Invalid {what} file {}, starting fresh: {err}", path.display());
+        T::default()
+    })
+}
+
+/// Upgrades a raw state document to `STATE_VERSION` one step at a time.
+/// Fields added by a version are filled with defaults on deserialization.
+fn migrate(mut value: Value) -> Result<Value> {
+    if !value.is_object() {
+        anyhow::bail!("state is not a JSON object");
+    }
+    // Files written before versioning was introduced carry no version field.
+    let mut version = value.get("version").and_then(Value::as_u64).unwrap_or(1);
+    if version > STATE_VERSION {
+        anyhow::bail!("unknown state version {version}, expected at most {STATE_VERSION}");
+    }
+    while version < STATE_VERSION {
+        // v1 -> v2 introduced the version field itself,
+        // v2 -> v3 added streak and last_win,
+        // v3 -> v4 added best_streak and best_kda,
+        // v4 -> v5 added last_match_start,
+        // v5 -> v6 added leaderboard_rank,
+        // v6 -> v7 added hero_last_played,
+        // v7 -> v8 added matches_played.
+        version += 1;
+    }
+    value["version"] = version.into();
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    /// A path in the system temp dir that no other test uses.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dotawatcher-{}-{name}.json", std::process::id()))
+    }
+
+    #[test]
+    fn state_survives_a_save_and_load() {
+        let path = temp_path("round-trip");
+        let mut state = PersistentState {
+            last_match_id: 7_654_321,
+            matches_played: 41,
+            ..Default::default()
+        };
+        state.record_result(true);
+        state.record_kda(KdaRecord { match_id: 7_654_321, kills: 12, deaths: 3, assists: 18 });
+        state.record_hero(8, 1_700_000_000);
+        state.save(&path).unwrap();
+
+        let loaded = PersistentState::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.version, STATE_VERSION);
+        assert_eq!(loaded.last_match_id, 7_654_321);
+        assert_eq!(loaded.streak, 1);
+        assert_eq!(loaded.last_win, Some(true));
+        assert_eq!(loaded.best_kda, state.best_kda);
+        assert_eq!(loaded.hero_last_played, BTreeMap::from([(8, 1_700_000_000)]));
+        assert_eq!(loaded.matches_played, 41);
+    }
+
+    #[test]
+    fn invalid_state_starts_fresh() {
+        let path = temp_path("invalid");
+        fs::write(&path, "{ not json").unwrap();
+        let loaded = PersistentState::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.last_match_id, 0);
+        assert_eq!(loaded.matches_played, 0);
+    }
+
+    #[test]
+    fn unversioned_state_migrates_with_defaults() {
+        let value = serde_json::json!({ "last_match_id": 123 });
+        let state: PersistentState = serde_json::from_value(migrate(value).unwrap()).unwrap();
+        assert_eq!(state.version, STATE_VERSION);
+        assert_eq!(state.last_match_id, 123);
+        assert_eq!(state.streak, 0);
+        assert_eq!(state.best_kda, None);
+        assert!(state.hero_last_played.is_empty());
+    }
+
+    #[test]
+    fn future_state_version_is_rejected() {
+        let value = serde_json::json!({ "version": STATE_VERSION + 1, "last_match_id": 123 });
+        assert!(migrate(value.clone()).is_err());
+
+        let path = temp_path("future");
+        fs::write(&path, value.to_string()).unwrap();
+        let loaded = PersistentState::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.last_match_id, 0);
+    }
+
+    #[test]
+    fn win_streak_records_start_after_the_first_win() {
+        let mut state = PersistentState::default();
+        assert_eq!(state.record_result(true), None);
+        assert_eq!(state.record_result(true), Some(NewRecord::Streak(2)));
+        assert_eq!(state.record_result(false), None);
+        assert_eq!(state.streak, -1);
+        // Tying the best isn't a record, beating it is.
+        for _ in 0..2 {
+            assert_eq!(state.record_result(true), None);
+        }
+        assert_eq!(state.record_result(true), Some(NewRecord::Streak(3)));
+        assert_eq!(state.best_streak, 3);
+    }
+
+    #[test]
+    fn kda_record_needs_a_better_ratio() {
+        let kda = |match_id, kills, deaths, assists| KdaRecord { match_id, kills, deaths, assists };
+        let mut state = PersistentState::default();
+        assert_eq!(state.record_kda(kda(1, 5, 5, 5)), None);
+        assert_eq!(state.best_kda, Some(kda(1, 5, 5, 5)));
+        assert_eq!(state.record_kda(kda(2, 10, 10, 10)), None);
+        assert_eq!(state.record_kda(kda(3, 8, 0, 4)), Some(NewRecord::Kda(kda(3, 8, 0, 4))));
+        assert_eq!(state.best_kda, Some(kda(3, 8, 0, 4)));
+    }
+
+    #[test]
+    fn match_count_milestones_fire_once() {
+        let mut state = PersistentState { matches_played: 98, ..Default::default() };
+        assert_eq!(state.record_match_count(&[100, 500]), None);
+        assert_eq!(state.record_match_count(&[100, 500]), Some(NewRecord::Milestone(100)));
+        assert_eq!(state.record_match_count(&[100, 500]), None);
+    }
+
+    #[test]
+    fn seen_matches_reject_duplicates() {
+        let mut seen = SeenMatches::default();
+        assert!(seen.insert(1, 3));
+        assert!(seen.insert(2, 3));
+        assert!(!seen.insert(1, 3));
+        assert_eq!(seen.ids, [1, 2]);
+    }
+
+    #[test]
+    fn seen_matches_shrink_to_a_lowered_cap() {
+        let mut seen = SeenMatches::default();
+        for match_id in 1..=5 {
+            seen.insert(match_id, 5);
+        }
+        assert!(seen.insert(6, 2));
+        assert_eq!(seen.ids, [5, 6]);
+        // Evicted ids count as new again.
+        assert!(seen.insert(1, 2));
+        assert_eq!(seen.ids, [6, 1]);
+    }
+
+    #[test]
+    fn owned_games_announce_only_additions_after_the_first_check() {
+        let mut owned = OwnedGames::default();
+        assert!(owned.update(BTreeSet::from([570, 730])).is_empty());
+        assert_eq!(owned.update(BTreeSet::from([570, 730, 440, 620])), vec![440, 620]);
+        // Games that left the library, e.g. refunds, come back as new.
+        assert!(owned.update(BTreeSet::from([570, 440, 620])).is_empty());
+        assert_eq!(owned.update(BTreeSet::from([570, 730, 440, 620])), vec![730]);
+    }
+
+
+    #[test]
+    fn only_newly_unlocked_achievements_come_back() {
+        let ids = |ids: &[&str]| ids.iter().map(ToString::to_string).collect::<BTreeSet<_>>();
+        let mut unlocked = UnlockedAchievements::default();
+        assert!(unlocked.update(570, ids(&["FIRST_BLOOD"])).is_empty());
+        assert_eq!(unlocked.update(570, ids(&["FIRST_BLOOD", "RAMPAGE"])), ["RAMPAGE"]);
+        assert!(unlocked.update(570, ids(&["FIRST_BLOOD", "RAMPAGE"])).is_empty());
+        // Another app is seeded on its own first check.
+        assert!(unlocked.update(730, ids(&["ACE"])).is_empty());
+    }
+
+
+    #[test]
+    fn leaderboard_rank_changes_are_reported_once() {
+        let mut state = PersistentState::default();
+        assert_eq!(state.record_leaderboard_rank(None), None);
+        assert_eq!(state.record_leaderboard_rank(Some(900)), Some(LeaderboardChange::Entered(900)));
+        assert_eq!(state.record_leaderboard_rank(Some(900)), None);
+        assert_eq!(
+            state.record_leaderboard_rank(Some(850)),
+            Some(LeaderboardChange::Moved { from: 900, to: 850 })
+        );
+        assert_eq!(state.record_leaderboard_rank(None), Some(LeaderboardChange::Left(850)));
+    }
+}
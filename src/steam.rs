@@ -0,0 +1,149 @@
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{ Duration, Instant };
+
+use anyhow::Result;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use crate::error::FetchError;
+use crate::{ http_client, STEAM_API_KEY };
+
+/// Steam64 ids are Steam32 ids offset by this base.
+pub const STEAM64_BASE: u64 = 76561197960265728;
+const STEAM_PLAYER_SUMMARIES_URL: &str =
+    "https://api.steampowered.com/ISteamUser/GetPlayerSummaries/v2/";
+/// `GetPlayerSummaries` takes at most this many ids per request.
+const SUMMARIES_PER_REQUEST: usize = 100;
+/// How long a key Steam rate limited or rejected is left alone.
+const KEY_COOLDOWN: Duration = Duration::from_secs(600);
+
+/// `STEAM_API_KEY` as one or more keys separated by commas. Requests use the
+/// first key that isn't cooling down after a 429 or 403.
+#[derive(Debug, Default)]
+pub struct SteamKeys {
+    keys: Vec<String>,
+    /// When each key in `keys` may be used again.
+    cooldowns: Mutex<Vec<Option<Instant>>>,
+}
+
+impl FromStr for SteamKeys {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let keys: Vec<String> = s
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(str::to_string)
+            .collect();
+        Ok(SteamKeys {
+            cooldowns: Mutex::new(vec![None; keys.len()]),
+            keys,
+        })
+    }
+}
+
+impl SteamKeys {
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// The index and value of the key to use next, if any isn't cooling down.
+    fn active(&self) -> Option<(usize, &str)> {
+        let now = Instant::now();
+        let cooldowns = self.cooldowns.lock().unwrap();
+        (0..self.keys.len())
+            .find(|&index| cooldowns[index].is_none_or(|until| until <= now))
+            .map(|index| (index, self.keys[index].as_str()))
+    }
+
+    /// Puts the key at `index` on cooldown if Steam answered with `status`
+    /// because of the key itself.
+    fn report(&self, index: usize, status: StatusCode) {
+        if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::FORBIDDEN {
+            self.cooldowns.lock().unwrap()[index] = Some(Instant::now() + KEY_COOLDOWN);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SummariesResponse {
+    response: Summaries,
+}
+
+#[derive(Debug, Deserialize)]
+struct Summaries {
+    #[serde(default)]
+    players: Vec<PlayerSummary>,
+}
+
+/// The public part of a Steam profile.
+#[derive(Debug, Deserialize)]
+pub struct PlayerSummary {
+    pub steamid: String,
+    pub personaname: String,
+    /// URL of the full size avatar.
+    #[serde(default)]
+    pub avatarfull: String,
+    /// 0 is offline, anything else some kind of online.
+    #[serde(default)]
+    pub personastate: u8,
+    /// Name of the game being played, if any.
+    #[serde(default)]
+    pub gameextrainfo: Option<String>,
+}
+
+/// GETs `url` from the Steam Web API with the active `STEAM_API_KEY` and
+/// decodes the JSON body.
+pub async fn steam_get_json<T: DeserializeOwned>(url: &str) -> Result<T, FetchError> {
+    let keys = STEAM_API_KEY.get().unwrap();
+    // Every key is cooling down after a 429 or 403.
+    let (index, key) = keys.active().ok_or(FetchError::RateLimited)?;
+    let url = format!("{url}&key={key}");
+    let body = async { http_client().get(url).send().await?.error_for_status()?.json().await }
+        .await
+        .map_err(|err: reqwest::Error| {
+            if let Some(status) = err.status() {
+                keys.report(index, status);
+            }
+            // The URL carries the key, keep it out of logged errors.
+            err.without_url()
+        })?;
+    Ok(body)
+}
+
+/// Profiles of the given Steam64 ids, in no particular order.
+pub async fn request_summaries(ids: &[String]) -> Result<Vec<PlayerSummary>, FetchError> {
+    let mut summaries = Vec::new();
+    for chunk in ids.chunks(SUMMARIES_PER_REQUEST) {
+        let url = format!("{STEAM_PLAYER_SUMMARIES_URL}?steamids={}", chunk.join(","));
+        let response: SummariesResponse = steam_get_json(&url).await?;
+        summaries.extend(response.response.players);
+    }
+    Ok(summaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_limited_key_hands_over_to_the_next() {
+        let keys: SteamKeys = "key-a, key-b".parse().unwrap();
+        assert_eq!(keys.active(), Some((0, "key-a")));
+        keys.report(0, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(keys.active(), Some((0, "key-a")));
+        keys.report(0, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(keys.active(), Some((1, "key-b")));
+        keys.report(1, StatusCode::FORBIDDEN);
+        assert_eq!(keys.active(), None);
+    }
+
+    #[test]
+    fn blank_keys_are_ignored() {
+        let keys: SteamKeys = " , ".parse().unwrap();
+        assert!(keys.is_empty());
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use tokio::time::{ self, Duration, Instant };
+
+use crate::error::FetchError;
+use crate::notifier::Announcement;
+use crate::steam::request_summaries;
+use crate::{
+    announce, http_client, read_capped, render_template, sanitize_activity_text, EventKind,
+    LOCALIZATION, STEAM_API_KEY, STEAM_GROUP_ID, STEAM_GROUP_MAX_MEMBERS,
+};
+
+const STEAM_COMMUNITY_URL: &str = "https://steamcommunity.com";
+const GROUP_POLL_INTERVAL: Duration = Duration::from_secs(300);
+/// Members join and leave rarely, so the list is only fetched again this often.
+const GROUP_REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+/// What was last seen of a group member.
+#[derive(Debug)]
+struct MemberState {
+    online: bool,
+    game: Option<String>,
+}
+
+/// Picks the Steam64 ids out of a group's `memberslistxml` page.
+fn parse_member_ids(xml: &str) -> Vec<String> {
+    xml.split("<steamID64>")
+        .skip(1)
+        .filter_map(|rest| rest.split_once("</steamID64>"))
+        .map(|(id, _)| id.trim().to_string())
+        .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()))
+        .collect()
+}
+
+/// The first `STEAM_GROUP_MAX_MEMBERS` members of the group.
+async fn request_members(group_id: u64) -> Result<Vec<String>, FetchError> {
+    let url = format!("{STEAM_COMMUNITY_URL}/gid/{group_id}/memberslistxml/?xml=1");
+    let response = http_client().get(&url).send().await?.error_for_status()?;
+    let body = read_capped(&url, response).await?;
+    let mut members = parse_member_ids(&body);
+    members.truncate(*STEAM_GROUP_MAX_MEMBERS.get().unwrap());
+    Ok(members)
+}
+
+/// The announcement for a member going from `previous` to `current`, if any.
+fn member_announcement(
+    name: &str,
+    previous: &MemberState,
+    current: &MemberState,
+) -> Option<String> {
+    let locals = LOCALIZATION.get().unwrap();
+    match &current.game {
+        Some(game) if previous.game.as_ref() != Some(game) => Some(render_template(
+            locals.group_member_playing.as_deref().unwrap_or("{name} is playing {game}"),
+            &[("name", name), ("game", &sanitize_activity_text(game))],
+        )),
+        _ if current.online && !previous.online => Some(render_template(
+            locals.group_member_online.as_deref()?,
+            &[("name", name)],
+        )),
+        _ => None,
+    }
+}
+
+/// Watches the members of `STEAM_GROUP_ID` and announces when they come
+/// online or start a game. The first poll only records what they're doing.
+pub async fn steam_group_loop() {
+    let group_id = *STEAM_GROUP_ID.get().unwrap();
+    if group_id == 0 {
+        return;
+    }
+    if STEAM_API_KEY.get().unwrap().is_empty() {
+        eprintln!("STEAM_GROUP_ID needs STEAM_API_KEY, group watching disabled");
+        return;
+    }
+
+    let mut members = Vec::new();
+    let mut members_fetched: Option<Instant> = None;
+    let mut states: HashMap<String, MemberState> = HashMap::new();
+    let mut interval = time::interval(GROUP_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if members_fetched.is_none_or(|fetched| fetched.elapsed() >= GROUP_REFRESH_INTERVAL) {
+            match request_members(group_id).await {
+                Ok(fetched) => {
+                    members = fetched;
+                    members_fetched = Some(Instant::now());
+                    // Members who left the group aren't watched anymore.
+                    states.retain(|id, _| members.contains(id));
+                }
+                Err(err) => eprintln!("Couldn't fetch members of Steam group {group_id}: {err}"),
+            }
+        }
+        if members.is_empty() {
+            continue;
+        }
+
+        let summaries = match request_summaries(&members).await {
+            Ok(summaries) => summaries,
+            Err(err) => {
+                eprintln!("Couldn't fetch Steam group member summaries: {err}");
+                continue;
+            }
+        };
+        for summary in summaries {
+            let current = MemberState {
+                online: summary.personastate != 0,
+                game: summary.gameextrainfo,
+            };
+            let name = sanitize_activity_text(&summary.personaname);
+            let content = states
+                .get(&summary.steamid)
+                .and_then(|previous| member_announcement(&name, previous, &current));
+            states.insert(summary.steamid, current);
+            if let Some(content) = content {
+                announce(Announcement::new(EventKind::Presence, content)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn member_ids_come_from_the_members_list() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<memberList>
+    <groupID64>103582791429521408</groupID64>
+    <groupDetails><groupName><![CDATA[Stack]]></groupName></groupDetails>
+    <memberCount>3</memberCount>
+    <members>
+        <steamID64>76561197960287930</steamID64>
+        <steamID64> 76561197960265728 </steamID64>
+        <steamID64>not-an-id</steamID64>
+        <steamID64></steamID64>
+    </members>
+</memberList>"#;
+        assert_eq!(parse_member_ids(xml), ["76561197960287930", "76561197960265728"]);
+        assert!(parse_member_ids("<html>Private group</html>").is_empty());
+    }
+}
@@ -0,0 +1,63 @@
+use tokio::time::{ self, Duration };
+
+use crate::notifier::Announcement;
+use crate::steam::{ request_summaries, PlayerSummary, STEAM64_BASE };
+use crate::{
+    announce, sanitize_activity_text, target_name, EventKind, LOCALIZATION, STEAM_API_KEY,
+    TARGET_STEAMID32,
+};
+
+const PROFILE_POLL_INTERVAL: Duration = Duration::from_secs(600);
+
+/// The announcements for the target's profile going from `previous` to `current`.
+fn profile_announcements(previous: &PlayerSummary, current: &PlayerSummary) -> Vec<String> {
+    let locals = LOCALIZATION.get().unwrap();
+    let target_name = target_name();
+    let mut announcements = Vec::new();
+    if current.personaname != previous.personaname {
+        let changed =
+            locals.steam_name_changed.as_deref().unwrap_or("changed their Steam name to");
+        let name = sanitize_activity_text(&current.personaname);
+        announcements.push(format!("{target_name} {changed} {name}"));
+    }
+    // An empty URL means the avatar was missing from the response, not changed.
+    if !current.avatarfull.is_empty() && current.avatarfull != previous.avatarfull {
+        let changed =
+            locals.steam_avatar_changed.as_deref().unwrap_or("changed their Steam avatar");
+        announcements.push(format!("{target_name} {changed}\n{}", current.avatarfull));
+    }
+    announcements
+}
+
+/// Announces the target changing their Steam persona name or avatar, when
+/// `STEAM_API_KEY` is set. The first poll only records the current profile.
+pub async fn steam_profile_loop() {
+    if STEAM_API_KEY.get().unwrap().is_empty() {
+        return;
+    }
+    let steam_id = (STEAM64_BASE + TARGET_STEAMID32.get().unwrap()).to_string();
+
+    let mut last_seen: Option<PlayerSummary> = None;
+    let mut interval = time::interval(PROFILE_POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let profile = match request_summaries(std::slice::from_ref(&steam_id)).await {
+            Ok(summaries) => summaries.into_iter().find(|summary| summary.steamid == steam_id),
+            Err(err) => {
+                eprintln!("Couldn't fetch the target's Steam profile: {err}");
+                continue;
+            }
+        };
+        let Some(profile) = profile else {
+            continue;
+        };
+        let announcements = last_seen
+            .as_ref()
+            .map(|previous| profile_announcements(previous, &profile))
+            .unwrap_or_default();
+        last_seen = Some(profile);
+        for content in announcements {
+            announce(Announcement::new(EventKind::Presence, content)).await;
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::atomic::{ AtomicU64, Ordering };
+
+use serenity::all::{ Activity, ActivityType };
+use tokio::sync::Mutex;
+
+use crate::notifier::Announcement;
+use crate::{
+    announce, announce_follow_up, clamp_message, render_template, sanitize_activity_text,
+    target_name, EventKind, DISCORD_CONTENT_LIMIT, LOCALIZATION, MAX_MESSAGE_LENGTH,
+};
+
+/// The stream currently announced, so title changes edit its messages in place.
+static ACTIVE_STREAM: Mutex<Option<ActiveStream>> = Mutex::const_new(None);
+
+struct ActiveStream {
+    title: String,
+    /// The edit key of the go-live announcement, unique to this stream.
+    key: String,
+    /// Whether the go-live announcement went out, so there is something to
+    /// edit and a reason to say the stream ended.
+    announced: bool,
+}
+
+pub fn is_streaming(activity: &Activity) -> bool {
+    activity.kind == ActivityType::Streaming
+}
+
+/// What the stream is about: its `details`, falling back to `state` and the
+/// platform name.
+fn title(activity: &Activity) -> String {
+    let title = [activity.details.as_deref(), activity.state.as_deref()]
+        .into_iter()
+        .flatten()
+        .find(|text| !text.trim().is_empty())
+        .unwrap_or(&activity.name);
+    sanitize_activity_text(title)
+}
+
+fn render(activity: &Activity, title: &str) -> String {
+    let template = LOCALIZATION.get().unwrap().stream_started.as_deref();
+    let url = activity.url.as_ref().map_or(String::new(), ToString::to_string);
+    // Discord only reports a party size for some streams; it's the closest
+    // thing to a viewer count the gateway offers.
+    let viewers = activity
+        .party
+        .as_ref()
+        .and_then(|party| party.size)
+        .map_or(String::new(), |[current, _]| current.to_string());
+    let content = render_template(
+        template.unwrap_or("{target_name} is streaming: {title} {url}"),
+        &[
+            ("target_name", &target_name()),
+            ("title", title),
+            ("url", &url),
+            ("viewers", &viewers),
+        ],
+    );
+    clamp_message(
+        content.trim(),
+        (*MAX_MESSAGE_LENGTH.get().unwrap()).min(DISCORD_CONTENT_LIMIT),
+    )
+}
+
+/// Posts a message when `activity` starts a stream, edits it when the title
+/// changes and follows up with a note once streaming stops. Callers skip the
+/// generic presence announcement while the target is streaming.
+pub async fn update(activity: Option<&Activity>) {
+    static STREAMS: AtomicU64 = AtomicU64::new(0);
+    let mut active = ACTIVE_STREAM.lock().await;
+    match (activity, active.as_mut()) {
+        (Some(activity), None) => {
+            let title = title(activity);
+            let key = format!("stream-{}", STREAMS.fetch_add(1, Ordering::Relaxed));
+            let content = render(activity, &title);
+            let go_live =
+                Announcement::new(EventKind::Presence, content).with_edit_key(key.clone());
+            let announced = announce(go_live).await;
+            *active = Some(ActiveStream { title, key, announced });
+        }
+        (Some(activity), Some(stream)) => {
+            let title = title(activity);
+            if title == stream.title {
+                return;
+            }
+            if stream.announced {
+                let content = render(activity, &title);
+                let edit = Announcement::new(EventKind::Presence, content)
+                    .with_edit_key(stream.key.clone());
+                announce_follow_up(edit).await;
+            }
+            stream.title = title;
+        }
+        (None, Some(_)) => {
+            let stream = active.take().unwrap();
+            if !stream.announced {
+                return;
+            }
+            let template = LOCALIZATION.get().unwrap().stream_ended.as_deref();
+            let content = render_template(
+                template.unwrap_or("{target_name}'s stream ended"),
+                &[("target_name", &target_name()), ("title", &stream.title)],
+            );
+            announce(Announcement::new(EventKind::Presence, content)).await;
+        }
+        (None, None) => {}
+    }
+}